@@ -0,0 +1,148 @@
+//! Turn a message log into redacted fixtures and a replay test.
+//!
+//! ```sh
+//! cargo run --example logfixtures -- /tmp/lennox.ndjson firmware_3_81 --salt "$FIXTURE_SALT"
+//! cargo test --test replay_firmware_3_81   # writes the snapshot, then fails
+//! cargo test --test replay_firmware_3_81   # compares against it
+//! ```
+//!
+//! Writes `tests/fixtures/<name>/*.json` and `tests/replay_<name>.rs`,
+//! refusing to replace an existing capture unless `--force` is given. Ids are
+//! hashed with the required `--salt`; keep it private, and reuse it so
+//! regenerating fixtures gives the same hashes. The test replays the fixtures through an offline client and compares the
+//! resulting systems and events with `tests/fixtures/<name>/snapshot.txt`,
+//! which it writes on the first run (or with `UPDATE_SNAPSHOTS=1`) for review.
+
+use std::env;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+use std::process::exit;
+
+use lennox_s30::{extract_fixtures, Fixture, LogHistory, RedactionPolicy};
+
+const USAGE: &str = "usage: logfixtures <log> <name> --salt <salt> [--root <crate dir>] [--force]";
+
+fn main() -> lennox_s30::Result<()> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (Some(log), Some(name)) = (args.first(), args.get(1)) else {
+        eprintln!("{USAGE}");
+        exit(2);
+    };
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_') {
+        eprintln!("name must be lowercase letters, digits and underscores\n{USAGE}");
+        exit(2);
+    }
+    let option = |flag: &str| args.iter().position(|a| a == flag).and_then(|i| args.get(i + 1));
+    let root = option("--root").map_or(".", String::as_str);
+    let Some(salt) = option("--salt").filter(|s| !s.is_empty() && !s.starts_with("--")) else {
+        eprintln!("--salt is required; ids hashed with a known salt can be looked up\n{USAGE}");
+        exit(2);
+    };
+    let force = args.iter().any(|a| a == "--force");
+
+    let dir = Path::new(root).join("tests/fixtures").join(name);
+    let test = Path::new(root).join(format!("tests/replay_{name}.rs"));
+    if !force && (dir.exists() || test.exists()) {
+        eprintln!("{} or {} already exists; pass --force to replace it", dir.display(), test.display());
+        exit(1);
+    }
+
+    let history = LogHistory::open(log)?;
+    let fixtures = extract_fixtures(history.polls(), RedactionPolicy::standard().salt(salt));
+    if fixtures.is_empty() {
+        eprintln!("no messages in {log}");
+        exit(1);
+    }
+
+    if dir.exists() {
+        // Drop fixtures from the previous capture; keep its snapshot to compare against.
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|e| e == "json") {
+                fs::remove_file(path)?;
+            }
+        }
+    }
+    fs::create_dir_all(&dir)?;
+    for fixture in &fixtures {
+        let json = serde_json::to_string_pretty(&fixture.message).expect("fixture serializes");
+        fs::write(dir.join(format!("{}.json", fixture.name)), json + "\n")?;
+    }
+    fs::write(&test, replay_test(name, &fixtures))?;
+
+    println!("wrote {} fixtures to {}", fixtures.len(), dir.display());
+    println!("wrote {}", test.display());
+    Ok(())
+}
+
+fn replay_test(name: &str, fixtures: &[Fixture]) -> String {
+    let mut includes = String::new();
+    for fixture in fixtures {
+        writeln!(includes, "    include_str!(\"fixtures/{name}/{}.json\"),", fixture.name).unwrap();
+    }
+    TEMPLATE.replace("{name}", name).replace("{includes}", &includes)
+}
+
+const TEMPLATE: &str = r#"//! Generated by `cargo run --example logfixtures -- <log> {name} --salt <salt>`.
+//!
+//! Replays `tests/fixtures/{name}/` through an offline client and compares the
+//! resulting systems and events with `snapshot.txt` next to the fixtures. The
+//! snapshot is written on the first run, or with `UPDATE_SNAPSHOTS=1`; review
+//! it before committing.
+
+use std::sync::{Arc, Mutex};
+
+use lennox_s30::{Event, RecordedPoll, Replayer, S30Client};
+use serde_json::{json, Value};
+
+/// In capture order.
+const FIXTURES: &[&str] = &[
+{includes}];
+
+const SNAPSHOT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/{name}/snapshot.txt");
+
+#[tokio::test]
+async fn {name}_replays_to_snapshot() {
+    let events: Arc<Mutex<Vec<Event>>> = Arc::default();
+    let mut client = S30Client::builder("127.0.0.1")
+        .on_event({
+            let events = events.clone();
+            move |e| events.lock().unwrap().push(e.clone())
+        })
+        .build();
+    let polls = FIXTURES
+        .iter()
+        .map(|fixture| RecordedPoll {
+            ts: chrono::Utc::now(),
+            status: 200,
+            body: Some(json!({"messages": [serde_json::from_str::<Value>(fixture).unwrap()]})),
+            keyframe: false,
+        })
+        .collect();
+    Replayer::from_polls(polls).instant().run(&mut client).await;
+
+    let mut systems = client.systems().to_vec();
+    for system in &mut systems {
+        system.last_updated = None;
+        system.zones.iter_mut().for_each(|z| z.last_updated = None);
+        system.equipments.iter_mut().for_each(|e| e.last_updated = None);
+    }
+    let events: Vec<Event> = events
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|e| !matches!(e, Event::ConnectionChanged { .. }))
+        .cloned()
+        .collect();
+    let actual = format!("{systems:#?}\n{events:#?}\n");
+
+    match std::fs::read_to_string(SNAPSHOT) {
+        Ok(expected) if std::env::var_os("UPDATE_SNAPSHOTS").is_none() => assert_eq!(actual, expected),
+        _ => {
+            std::fs::write(SNAPSHOT, &actual).unwrap();
+            panic!("wrote {SNAPSHOT}; review it and run again");
+        }
+    }
+}
+"#;
@@ -0,0 +1,72 @@
+//! Query a message log written with `message_log`.
+//!
+//! ```sh
+//! # Full state at 3 AM
+//! cargo run --example logtool -- state /tmp/lennox.ndjson 2024-01-15T03:00:00-07:00
+//! # Parsed systems instead of raw JSON
+//! cargo run --example logtool -- state /tmp/lennox.ndjson 2024-01-15T03:00:00-07:00 --typed
+//! # Every change to zone 0's heat setpoint overnight
+//! cargo run --example logtool -- history /tmp/lennox.ndjson /zones/0/status/period/hsp \
+//!     --from 2024-01-14T22:00:00-07:00 --to 2024-01-15T08:00:00-07:00
+//! ```
+
+use std::env;
+use std::process::exit;
+
+use chrono::{DateTime, Utc};
+use lennox_s30::LogHistory;
+
+const USAGE: &str = "usage:
+  logtool state <log> <time> [--typed]
+  logtool history <log> <json-pointer> [--from <time>] [--to <time>]
+times are RFC 3339, e.g. 2024-01-15T03:00:00-07:00";
+
+fn parse_time(s: &str) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(s)
+        .unwrap_or_else(|e| {
+            eprintln!("invalid time {s:?}: {e}\n{USAGE}");
+            exit(2);
+        })
+        .with_timezone(&Utc)
+}
+
+fn option<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|a| a == name)
+        .and_then(|i| args.get(i + 1))
+        .map(String::as_str)
+}
+
+fn main() -> lennox_s30::Result<()> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (Some(command), Some(log), Some(target)) = (args.first(), args.get(1), args.get(2)) else {
+        eprintln!("{USAGE}");
+        exit(2);
+    };
+    let history = LogHistory::open(log)?;
+
+    match command.as_str() {
+        "state" => {
+            let at = parse_time(target);
+            if args.iter().any(|a| a == "--typed") {
+                println!("{:#?}", history.systems_at(at));
+            } else {
+                let state = history.state_at(at);
+                println!("{}", serde_json::to_string_pretty(&state).expect("state serializes"));
+            }
+        }
+        "history" => {
+            let from = option(&args, "--from").map(parse_time).unwrap_or(DateTime::<Utc>::MIN_UTC);
+            let to = option(&args, "--to").map(parse_time).unwrap_or(DateTime::<Utc>::MAX_UTC);
+            for change in history.path_history(target, from..=to) {
+                let show = |v: &Option<serde_json::Value>| v.as_ref().map_or("-".to_string(), |v| v.to_string());
+                println!("{}  {} -> {}", change.ts.to_rfc3339(), show(&change.old), show(&change.new));
+            }
+        }
+        _ => {
+            eprintln!("{USAGE}");
+            exit(2);
+        }
+    }
+    Ok(())
+}
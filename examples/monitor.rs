@@ -1,6 +1,5 @@
-use lennox_s30::{MessageLogMode, S30Client};
+use lennox_s30::{LogRotation, MessageLogMode, Protocol, RedactionPolicy, S30Client};
 use std::env;
-use std::time::Duration;
 
 #[tokio::main]
 async fn main() -> lennox_s30::Result<()> {
@@ -9,8 +8,10 @@ async fn main() -> lennox_s30::Result<()> {
     let args: Vec<String> = env::args().collect();
     let ip = args
         .get(1)
-        .expect("usage: monitor <ip> [--app-id <id>] [--http] [--log <path>] [--log-diff <path>]");
+        .expect("usage: monitor <ip> [--app-id <id>] [--http] [--log <path>] [--log-diff <path>] [--rotate] [--redact]");
     let use_http = args.iter().any(|a| a == "--http");
+    let rotate = args.iter().any(|a| a == "--rotate");
+    let redact = args.iter().any(|a| a == "--redact");
     let app_id = args
         .iter()
         .position(|a| a == "--app-id")
@@ -52,7 +53,7 @@ async fn main() -> lennox_s30::Result<()> {
         });
 
     if use_http {
-        builder = builder.protocol("http");
+        builder = builder.protocol(Protocol::Http);
     }
     if let Some(id) = app_id {
         builder = builder.app_id(id);
@@ -64,19 +65,18 @@ async fn main() -> lennox_s30::Result<()> {
         println!("Logging messages (diffed) to {path}");
         builder = builder.message_log(MessageLogMode::Diffed, path);
     }
-
-    let mut client = builder.build();
+    if rotate {
+        builder = builder.message_log_rotation(LogRotation::default());
+    }
+    if redact {
+        builder = builder.message_log_redaction(RedactionPolicy::standard());
+    }
 
     println!("Connecting to {ip}...");
-    client.connect().await?;
-    println!("Connected. Polling for updates...");
+    let handle = builder.try_build()?.spawn();
 
-    loop {
-        if let Err(e) = client.poll().await {
-            eprintln!("Poll error: {e}");
-            tokio::time::sleep(Duration::from_secs(5)).await;
-            println!("Reconnecting...");
-            client.connect().await?;
-        }
-    }
+    tokio::signal::ctrl_c().await?;
+    println!("Disconnecting...");
+    handle.shutdown().await;
+    Ok(())
 }
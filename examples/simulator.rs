@@ -0,0 +1,31 @@
+//! Run a simulated thermostat for local development.
+//!
+//! ```sh
+//! cargo run --example simulator --features simulator -- 127.0.0.1:8080 tests/fixtures/system_heatpump_furnace.json
+//! cargo run --example monitor -- 127.0.0.1:8080 --http
+//! ```
+
+use std::net::SocketAddr;
+
+use lennox_s30::Simulator;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    tracing_subscriber::fmt::init();
+
+    let mut args = std::env::args().skip(1);
+    let addr: SocketAddr = args
+        .next()
+        .unwrap_or_else(|| "127.0.0.1:8080".to_string())
+        .parse()?;
+
+    let mut builder = Simulator::builder().bind(addr);
+    for fixture in args {
+        builder = builder.fixture(fixture);
+    }
+    let sim = builder.start().await?;
+    println!("simulator listening on {}", sim.host());
+
+    tokio::signal::ctrl_c().await?;
+    Ok(())
+}
@@ -1,4 +1,4 @@
-use lennox_s30::{Event, MessageLogMode, S30Client, Temperature};
+use lennox_s30::{Event, MessageLogMode, Protocol, S30Client, Temperature};
 use std::env;
 use std::future::Future;
 use std::io::{self, BufRead, Write as _};
@@ -31,7 +31,7 @@ async fn main() -> lennox_s30::Result<()> {
     });
 
     if use_http {
-        builder = builder.protocol("http");
+        builder = builder.protocol(Protocol::Http);
     }
 
     let log_path = if !no_log {
@@ -45,7 +45,7 @@ async fn main() -> lennox_s30::Result<()> {
         None
     };
 
-    let mut client = builder.build();
+    let mut client = builder.try_build()?;
 
     println!("Connecting to {ip}...");
     client.connect().await?;
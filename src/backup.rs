@@ -0,0 +1,306 @@
+use std::fmt;
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::client::{validate_parameter, S30Client};
+use crate::types::{Parameter, System};
+use crate::{Error, Result};
+
+/// Format version written to every backup. Bumped on incompatible changes.
+pub const BACKUP_VERSION: u32 = 1;
+
+/// Snapshot of every parameter on every piece of equipment.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ParameterBackup {
+    pub version: u32,
+    pub created: DateTime<Utc>,
+    pub equipments: Vec<EquipmentBackup>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EquipmentBackup {
+    pub id: u16,
+    pub equip_type: u16,
+    pub parameters: Vec<Parameter>,
+}
+
+impl ParameterBackup {
+    pub fn from_systems(systems: &[System]) -> Self {
+        let equipments = systems
+            .iter()
+            .flat_map(|s| &s.equipments)
+            .map(|e| EquipmentBackup {
+                id: e.id,
+                equip_type: e.equip_type,
+                parameters: e.parameters.values().cloned().collect(),
+            })
+            .collect();
+        Self {
+            version: BACKUP_VERSION,
+            created: Utc::now(),
+            equipments,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("backup serializes to JSON")
+    }
+
+    pub fn from_json(json: &str) -> Result<Self> {
+        let backup: Self =
+            serde_json::from_str(json).map_err(|e| Error::InvalidBackup(e.to_string()))?;
+        if backup.version != BACKUP_VERSION {
+            return Err(Error::InvalidBackup(format!(
+                "unsupported version {} (expected {BACKUP_VERSION})",
+                backup.version
+            )));
+        }
+        Ok(backup)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        std::fs::write(path, self.to_json())?;
+        Ok(())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        Self::from_json(&std::fs::read_to_string(path)?)
+    }
+
+    /// Compare the backup against live state without sending anything.
+    pub fn plan(&self, systems: &[System]) -> RestoreReport {
+        let mut entries = Vec::new();
+
+        for saved_equip in &self.equipments {
+            let live = systems
+                .iter()
+                .flat_map(|s| &s.equipments)
+                .find(|e| e.id == saved_equip.id);
+
+            for saved in &saved_equip.parameters {
+                let mut entry = RestoreEntry {
+                    equipment_id: saved_equip.id,
+                    pid: saved.pid,
+                    name: saved.name.clone(),
+                    current: None,
+                    saved: saved.value.clone(),
+                    action: RestoreAction::Unchanged,
+                };
+
+                let live_equip = match live {
+                    Some(e) if e.equip_type == saved_equip.equip_type => e,
+                    Some(e) => {
+                        entry.action = RestoreAction::Skipped(format!(
+                            "equipment type changed ({} -> {})",
+                            saved_equip.equip_type, e.equip_type
+                        ));
+                        entries.push(entry);
+                        continue;
+                    }
+                    None => {
+                        entry.action = RestoreAction::Skipped("equipment not present".to_string());
+                        entries.push(entry);
+                        continue;
+                    }
+                };
+
+                let Some(param) = live_equip.parameter(saved.pid) else {
+                    entry.action = RestoreAction::Skipped("parameter not present".to_string());
+                    entries.push(entry);
+                    continue;
+                };
+                entry.current = Some(param.value.clone());
+
+                entry.action = if param.value == saved.value {
+                    RestoreAction::Unchanged
+                } else if !param.enabled {
+                    RestoreAction::Skipped("parameter is read-only (enabled=false)".to_string())
+                } else {
+                    match validate_parameter(param, &saved.value) {
+                        Ok(_) => RestoreAction::Change,
+                        Err(reason) => RestoreAction::Skipped(reason),
+                    }
+                };
+                entries.push(entry);
+            }
+        }
+
+        RestoreReport { entries }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RestoreAction {
+    Unchanged,
+    /// Differs from live and passes validation; sent unless dry-running.
+    Change,
+    Applied,
+    Skipped(String),
+    Failed(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RestoreEntry {
+    pub equipment_id: u16,
+    pub pid: u16,
+    pub name: String,
+    pub current: Option<String>,
+    pub saved: String,
+    pub action: RestoreAction,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RestoreReport {
+    pub entries: Vec<RestoreEntry>,
+}
+
+impl RestoreReport {
+    /// Entries that differ from live state and would be (or were) written.
+    pub fn changes(&self) -> impl Iterator<Item = &RestoreEntry> {
+        self.entries
+            .iter()
+            .filter(|e| matches!(e.action, RestoreAction::Change | RestoreAction::Applied))
+    }
+
+    pub fn has_failures(&self) -> bool {
+        self.entries
+            .iter()
+            .any(|e| matches!(e.action, RestoreAction::Failed(_)))
+    }
+}
+
+impl fmt::Display for RestoreReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for e in &self.entries {
+            let current = e.current.as_deref().unwrap_or("-");
+            let action = match &e.action {
+                RestoreAction::Unchanged => continue,
+                RestoreAction::Change => "change".to_string(),
+                RestoreAction::Applied => "applied".to_string(),
+                RestoreAction::Skipped(reason) => format!("skipped: {reason}"),
+                RestoreAction::Failed(reason) => format!("failed: {reason}"),
+            };
+            writeln!(
+                f,
+                "equipment {} pid {} ({}): {current} -> {} [{action}]",
+                e.equipment_id, e.pid, e.name, e.saved
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl S30Client {
+    /// Export every parameter on every piece of equipment.
+    pub fn export_parameters(&self) -> ParameterBackup {
+        ParameterBackup::from_systems(self.systems())
+    }
+
+    /// Write back every saved value that differs from live state. Each change is
+    /// validated against the live descriptor first; use `ParameterBackup::plan`
+    /// for a dry run.
+    pub async fn restore_parameters(&mut self, backup: &ParameterBackup) -> Result<RestoreReport> {
+        let mut report = backup.plan(self.systems());
+
+        for entry in &mut report.entries {
+            if entry.action != RestoreAction::Change {
+                continue;
+            }
+            entry.action = match self
+                .set_equipment_parameter(entry.equipment_id, entry.pid, &entry.saved)
+                .await
+            {
+                Ok(()) => RestoreAction::Applied,
+                Err(e) => RestoreAction::Failed(e.to_string()),
+            };
+        }
+
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Descriptor, Equipment};
+
+    fn param(pid: u16, value: &str, enabled: bool) -> Parameter {
+        Parameter {
+            pid,
+            name: format!("param {pid}"),
+            value: value.to_string(),
+            enabled,
+            descriptor: Descriptor::Range { min: 0.0, max: 100.0, inc: 5.0, unit: "F".to_string() },
+        }
+    }
+
+    fn system_with(params: Vec<Parameter>) -> System {
+        System {
+            equipments: vec![Equipment {
+                id: 1,
+                equip_type: 19,
+                parameters: params.into_iter().map(|p| (p.pid, p)).collect(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn json_roundtrip() {
+        let backup = ParameterBackup::from_systems(&[system_with(vec![param(128, "50", true)])]);
+        let restored = ParameterBackup::from_json(&backup.to_json()).unwrap();
+        assert_eq!(backup, restored);
+    }
+
+    #[test]
+    fn rejects_unknown_version() {
+        let mut backup = ParameterBackup::from_systems(&[]);
+        backup.version = 99;
+        let err = ParameterBackup::from_json(&backup.to_json()).unwrap_err();
+        assert!(matches!(err, Error::InvalidBackup(_)));
+    }
+
+    #[test]
+    fn plan_only_changes_differences() {
+        let saved = ParameterBackup::from_systems(&[system_with(vec![
+            param(128, "50", true),
+            param(129, "25", true),
+            param(130, "10", false),
+            param(131, "40", true),
+        ])]);
+        let live = system_with(vec![
+            param(128, "50", true),
+            param(129, "30", true),
+            param(130, "15", false),
+        ]);
+
+        let report = saved.plan(&[live]);
+        let action = |pid| &report.entries.iter().find(|e| e.pid == pid).unwrap().action;
+
+        assert_eq!(action(128), &RestoreAction::Unchanged);
+        assert_eq!(action(129), &RestoreAction::Change);
+        assert!(matches!(action(130), RestoreAction::Skipped(_)));
+        assert!(matches!(action(131), RestoreAction::Skipped(_)));
+        assert_eq!(report.changes().count(), 1);
+    }
+
+    #[test]
+    fn plan_skips_values_failing_validation() {
+        let saved = ParameterBackup::from_systems(&[system_with(vec![param(128, "52", true)])]);
+        let live = system_with(vec![param(128, "50", true)]);
+        let report = saved.plan(&[live]);
+        assert!(matches!(report.entries[0].action, RestoreAction::Skipped(_)));
+    }
+
+    #[test]
+    fn plan_skips_swapped_equipment_type() {
+        let saved = ParameterBackup::from_systems(&[system_with(vec![param(128, "45", true)])]);
+        let mut live = system_with(vec![param(128, "50", true)]);
+        live.equipments[0].equip_type = 7;
+        let report = saved.plan(&[live]);
+        assert!(matches!(report.entries[0].action, RestoreAction::Skipped(_)));
+    }
+}
@@ -1,22 +1,44 @@
-use std::time::Instant;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use chrono::{DateTime, Utc};
 use serde_json::{Map, Value};
-use tracing::{debug, trace};
-
+use tokio::sync::{broadcast, watch};
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::{Stream, StreamExt};
+use tracing::{debug, trace, warn};
+
+use crate::command;
+use crate::confirm::{Confirmation, Expectation};
+use crate::driver::ReconnectPolicy;
 use crate::diff::{diff_json, generic_event, map_typed_event, Scope};
-use crate::logger::{MessageLogMode, MessageLogger};
+use crate::logger::{LogOptions, MessageLogMode, MessageLogger, RequestOutcome};
+use crate::redact::RedactionPolicy;
+use crate::sink::{LogCompression, LogRotation, MessageSink};
+use crate::params::{ParamKey, ParamValue};
+use crate::pinning::{FingerprintStore, TofuVerifier};
 use crate::protocol::{
-    manual_schedule_id, override_schedule_id, parse_retrieve_response, subscribe_message,
-    DEFAULT_APP_ID,
+    override_schedule_id, parse_retrieve, parse_retrieve_value, RetrieveBody,
+    subscribe_message, DEFAULT_APP_ID,
 };
+use crate::transport::{status_error, Transport};
 use crate::types::*;
 use crate::{Error, Result};
 
-const DEADBAND_C: f64 = 1.5;
-
 type EventCallback = Box<dyn Fn(&Event) + Send + Sync>;
 type SnapshotCallback = Box<dyn Fn(&System) + Send + Sync>;
 
+const DEFAULT_EVENT_BUFFER: usize = 256;
+
+const DEFAULT_LONG_POLL_TIMEOUT: Duration = Duration::from_secs(15);
+const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_TCP_KEEPALIVE: Duration = Duration::from_secs(60);
+
+/// How long without data before `DataStale` fires and RequestData is re-sent.
+const DEFAULT_STALE_AFTER: Duration = Duration::from_secs(600);
+
 const DIAG_COOLDOWN_SECS: u64 = 300;
 const DIAG_MAX_ATTEMPTS_PER_HOUR: u8 = 3;
 
@@ -70,33 +92,93 @@ impl DiagEnforcer {
     }
 }
 
+/// Longest app_id accepted by `try_build`.
+const MAX_APP_ID_LEN: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Protocol {
+    Http,
+    /// The thermostat's native scheme, with a self-signed certificate.
+    #[default]
+    Https,
+}
+
+impl Protocol {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Protocol::Http => "http",
+            Protocol::Https => "https",
+        }
+    }
+}
+
+impl std::fmt::Display for Protocol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for Protocol {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "http" => Ok(Protocol::Http),
+            "https" => Ok(Protocol::Https),
+            _ => Err(Error::InvalidProtocol(s.to_string())),
+        }
+    }
+}
+
 pub struct S30ClientBuilder {
     ip: String,
-    protocol: String,
+    protocol: Protocol,
     app_id: Option<String>,
     event_callbacks: Vec<EventCallback>,
     snapshot_callbacks: Vec<SnapshotCallback>,
     log_mode: Option<MessageLogMode>,
     log_path: Option<String>,
+    log_sink: Option<Box<dyn MessageSink>>,
+    log_options: LogOptions,
     diag_level: Option<u8>,
+    event_buffer: usize,
+    reconnect_policy: ReconnectPolicy,
+    stale_after: Duration,
+    long_poll_timeout: Duration,
+    request_timeout: Duration,
+    connect_timeout: Duration,
+    tcp_keepalive: Option<Duration>,
+    http_client: Option<reqwest::Client>,
+    fingerprint_store: Option<Arc<dyn FingerprintStore>>,
 }
 
 impl S30ClientBuilder {
     pub fn new(ip: impl Into<String>) -> Self {
         Self {
             ip: ip.into(),
-            protocol: "https".to_string(),
+            protocol: Protocol::default(),
             app_id: None,
             event_callbacks: Vec::new(),
             snapshot_callbacks: Vec::new(),
             log_mode: None,
             log_path: None,
+            log_sink: None,
+            log_options: LogOptions::default(),
             diag_level: None,
+            event_buffer: DEFAULT_EVENT_BUFFER,
+            reconnect_policy: ReconnectPolicy::default(),
+            stale_after: DEFAULT_STALE_AFTER,
+            long_poll_timeout: DEFAULT_LONG_POLL_TIMEOUT,
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            tcp_keepalive: Some(DEFAULT_TCP_KEEPALIVE),
+            http_client: None,
+            fingerprint_store: None,
         }
     }
 
-    pub fn protocol(mut self, proto: &str) -> Self {
-        self.protocol = proto.to_string();
+    pub fn protocol(mut self, protocol: Protocol) -> Self {
+        self.protocol = protocol;
         self
     }
 
@@ -118,6 +200,46 @@ impl S30ClientBuilder {
     pub fn message_log(mut self, mode: MessageLogMode, path: impl Into<String>) -> Self {
         self.log_mode = Some(mode);
         self.log_path = Some(path.into());
+        self.log_sink = None;
+        self
+    }
+
+    /// Log messages to `sink` instead of a file, e.g. a `RingBufferSink`
+    /// holding the last hour in memory. Rotation and compression only apply
+    /// to files; redaction applies to every sink.
+    pub fn message_sink(mut self, mode: MessageLogMode, sink: impl MessageSink + 'static) -> Self {
+        self.log_mode = Some(mode);
+        self.log_sink = Some(Box::new(sink));
+        self.log_path = None;
+        self
+    }
+
+    /// Rotate the message log by size or day, starting each file with a keyframe.
+    /// Without this the log is a single file that grows forever.
+    pub fn message_log_rotation(mut self, rotation: LogRotation) -> Self {
+        self.log_options.rotation = Some(rotation);
+        self
+    }
+
+    /// Write a keyframe, the full accumulated state, after every `every` poll
+    /// bodies, whatever the sink. Diffed entries after a lost or damaged line
+    /// can be rebuilt again from the next keyframe. Defaults to 1000; `None`
+    /// turns periodic keyframes off.
+    pub fn message_log_keyframes(mut self, every: Option<u32>) -> Self {
+        self.log_options.keyframe_every = every;
+        self
+    }
+
+    /// Compress the message log. Needs the `gzip` or `zstd` feature.
+    pub fn message_log_compression(mut self, compression: LogCompression) -> Self {
+        self.log_options.compression = compression;
+        self
+    }
+
+    /// Hash or mask sensitive fields before they reach the message log, e.g.
+    /// `RedactionPolicy::standard()`.
+    pub fn message_log_redaction(mut self, policy: RedactionPolicy) -> Self {
+        self.log_options.redaction = Some(policy);
         self
     }
 
@@ -126,47 +248,172 @@ impl S30ClientBuilder {
         self
     }
 
+    /// Events buffered per `subscribe()` stream before a slow subscriber starts
+    /// missing them. Defaults to 256.
+    pub fn event_buffer(mut self, capacity: usize) -> Self {
+        self.event_buffer = capacity.max(1);
+        self
+    }
+
+    /// Backoff used by `S30Client::spawn` when connecting or polling fails.
+    pub fn reconnect_policy(mut self, policy: ReconnectPolicy) -> Self {
+        self.reconnect_policy = policy;
+        self
+    }
+
+    /// Emit `Event::DataStale` once no data has arrived for this long, and
+    /// re-issue the RequestData subscription every period while it lasts.
+    /// Defaults to 10 minutes.
+    pub fn stale_after(mut self, window: Duration) -> Self {
+        self.stale_after = window;
+        self
+    }
+
+    /// How long the thermostat may hold a Retrieve open before answering 204.
+    /// Whole seconds; defaults to 15.
+    pub fn long_poll_timeout(mut self, timeout: Duration) -> Self {
+        self.long_poll_timeout = timeout.max(Duration::from_secs(1));
+        self
+    }
+
+    /// Deadline for each HTTP request. Retrieve gets this on top of the
+    /// long-poll timeout. Defaults to 10 seconds.
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = timeout;
+        self
+    }
+
+    /// TCP connect deadline. Defaults to 10 seconds. Ignored with `http_client`.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// TCP keepalive interval, or `None` to disable. Defaults to 60 seconds.
+    /// Ignored with `http_client`.
+    pub fn tcp_keepalive(mut self, interval: Option<Duration>) -> Self {
+        self.tcp_keepalive = interval;
+        self
+    }
+
+    /// Use a preconfigured client (proxies, custom resolvers). It must accept
+    /// the thermostat's self-signed certificate when using HTTPS. Request and
+    /// long-poll timeouts still apply per request.
+    pub fn http_client(mut self, client: reqwest::Client) -> Self {
+        self.http_client = Some(client);
+        self
+    }
+
+    /// Trust on first use: record the thermostat's certificate fingerprint in
+    /// `store` on first connect and refuse any other certificate afterwards
+    /// with `Error::CertificateMismatch`. Without this, any certificate is
+    /// accepted. Not applied to a client passed to `http_client`.
+    pub fn pin_certificate(mut self, store: impl FingerprintStore + 'static) -> Self {
+        self.fingerprint_store = Some(Arc::new(store));
+        self
+    }
+
+    /// Build the client, panicking on invalid configuration. See `try_build`.
     pub fn build(self) -> S30Client {
-        let http = reqwest::Client::builder()
-            .danger_accept_invalid_certs(true)
-            .build()
-            .expect("failed to build HTTP client");
-
-        let logger = match (self.log_mode, self.log_path) {
-            (Some(mode), Some(path)) => {
-                Some(MessageLogger::new(mode, &path).expect("failed to open log file"))
+        self.try_build().expect("invalid S30 client configuration")
+    }
+
+    /// Validate the configuration and build the client.
+    pub fn try_build(self) -> Result<S30Client> {
+        let base_url = validate_host(self.protocol, &self.ip)?;
+        let app_id = match self.app_id {
+            Some(id) => validate_app_id(id)?,
+            None => DEFAULT_APP_ID.to_string(),
+        };
+        validate_reconnect_policy(&self.reconnect_policy)?;
+
+        let logger = match (self.log_mode, self.log_sink, self.log_path) {
+            (Some(mode), Some(sink), _) => Some(MessageLogger::new(mode, sink, self.log_options)),
+            (Some(mode), None, Some(path)) => {
+                let logger = MessageLogger::open(mode, &path, self.log_options);
+                Some(logger.map_err(|e| Error::InvalidLogPath { path, reason: e.to_string() })?)
             }
             _ => None,
         };
 
-        S30Client {
-            http,
-            base_url: format!("{}://{}", self.protocol, self.ip),
-            app_id: self.app_id.unwrap_or_else(|| DEFAULT_APP_ID.to_string()),
-            connected: false,
+        let tls = self
+            .fingerprint_store
+            .map(|store| Arc::new(TofuVerifier::new(self.ip.clone(), store)));
+
+        let http = match self.http_client {
+            Some(client) => {
+                if tls.is_some() {
+                    warn!("pin_certificate has no effect with a custom http_client");
+                }
+                client
+            }
+            None => {
+                let builder = reqwest::Client::builder()
+                    .connect_timeout(self.connect_timeout)
+                    .tcp_keepalive(self.tcp_keepalive);
+                let builder = match &tls {
+                    Some(verifier) => builder.use_preconfigured_tls(verifier.clone().client_config()),
+                    None => builder.danger_accept_invalid_certs(true),
+                };
+                builder.build()?
+            }
+        };
+
+        let (event_tx, _) = broadcast::channel(self.event_buffer);
+        let (snapshot_tx, _) = watch::channel(Vec::new());
+
+        Ok(S30Client {
+            transport: Arc::new(Transport::new(
+                http,
+                base_url,
+                app_id,
+                self.request_timeout,
+                self.long_poll_timeout,
+                logger,
+                tls,
+            )),
             systems: Vec::new(),
             previous_json: Value::Object(Map::new()),
             event_callbacks: self.event_callbacks,
             snapshot_callbacks: self.snapshot_callbacks,
-            logger,
             diag_enforcer: self.diag_level.map(DiagEnforcer::new),
             diag_reassert_needed: false,
-        }
+            event_tx,
+            snapshot_tx,
+            reconnect_policy: self.reconnect_policy,
+            last_poll_status: None,
+            stale_after: self.stale_after,
+            data_seen_at: Instant::now(),
+            resubscribed_at: Instant::now(),
+            stale_reported: false,
+        })
     }
 }
 
+/// Map a broadcast receiver to an event stream, reporting overruns as `Event::Lagged`.
+pub(crate) fn event_stream(rx: broadcast::Receiver<Event>) -> impl Stream<Item = Event> + Send + 'static {
+    BroadcastStream::new(rx).map(|item| match item {
+        Ok(event) => event,
+        Err(BroadcastStreamRecvError::Lagged(missed)) => Event::Lagged { missed },
+    })
+}
+
 pub struct S30Client {
-    http: reqwest::Client,
-    base_url: String,
-    app_id: String,
-    connected: bool,
+    transport: Arc<Transport>,
     systems: Vec<System>,
     previous_json: Value,
     event_callbacks: Vec<EventCallback>,
     snapshot_callbacks: Vec<SnapshotCallback>,
-    logger: Option<MessageLogger>,
     diag_enforcer: Option<DiagEnforcer>,
     diag_reassert_needed: bool,
+    event_tx: broadcast::Sender<Event>,
+    snapshot_tx: watch::Sender<Vec<System>>,
+    reconnect_policy: ReconnectPolicy,
+    last_poll_status: Option<u16>,
+    stale_after: Duration,
+    data_seen_at: Instant,
+    resubscribed_at: Instant,
+    stale_reported: bool,
 }
 
 impl S30Client {
@@ -175,124 +422,336 @@ impl S30Client {
     }
 
     pub async fn connect(&mut self) -> Result<()> {
-        let connect_url = format!("{}/Endpoints/{}/Connect", self.base_url, self.app_id);
-        debug!(url = %connect_url, "connecting to S30");
-
-        let connect_path = format!("/Endpoints/{}/Connect", self.app_id);
-        if let Some(ref mut logger) = self.logger {
-            logger.log_request("POST", &connect_path, None);
-        }
-
-        self.http
-            .post(&connect_url)
-            .send()
-            .await?
-            .error_for_status()?;
-
-        let subscribe_url = format!("{}/Messages/RequestData", self.base_url);
-        let msg = subscribe_message(&self.app_id);
-        debug!(url = %subscribe_url, "subscribing to data");
+        self.set_state(|h| {
+            h.state = match h.state {
+                ConnectionState::Disconnected | ConnectionState::Connecting => ConnectionState::Connecting,
+                _ => ConnectionState::Reconnecting,
+            }
+        });
+        let health = self.transport.health();
+        let result = self
+            .subscribe_endpoint()
+            .await
+            .map_err(|e| self.transport.check_tls(e));
+        let event = match health.state {
+            ConnectionState::Reconnecting => "reconnect",
+            _ => "connect",
+        };
+        let error = result.as_ref().err().map(|e| e.to_string());
+        self.transport
+            .log(|l| l.log_connection(event, Some(health.consecutive_failures + 1), error.as_deref()));
+        self.set_state(|h| match result {
+            Ok(()) => {
+                h.state = ConnectionState::Subscribed;
+                h.consecutive_failures = 0;
+            }
+            Err(_) => {
+                h.consecutive_failures += 1;
+                if h.state == ConnectionState::Connecting {
+                    h.state = ConnectionState::Disconnected;
+                }
+            }
+        });
+        result
+    }
 
-        if let Some(ref mut logger) = self.logger {
-            logger.log_request("POST", "/Messages/RequestData", Some(&msg));
-        }
+    async fn subscribe_endpoint(&mut self) -> Result<()> {
+        let connect_path = format!("/Endpoints/{}/Connect", self.transport.app_id);
+        debug!(base_url = %self.transport.base_url, path = %connect_path, "connecting to S30");
+        self.transport.post_logged(&connect_path, None).await?;
 
-        self.http
-            .post(&subscribe_url)
-            .json(&msg)
-            .send()
-            .await?
-            .error_for_status()?;
+        self.request_data().await?;
 
         if let Some(ref mut enforcer) = self.diag_enforcer {
             let data = crate::protocol::set_diag_level_data(enforcer.target_level);
-            let msg = crate::protocol::command_message(&self.app_id, data.clone());
-            let url = format!("{}/Messages/Publish", self.base_url);
-            if let Some(ref mut logger) = self.logger {
-                logger.log_command("set_diag_level", None, &data);
-            }
-            self.http.post(&url).json(&msg).send().await?.error_for_status()?;
+            self.transport.publish_message("set_diag_level", None, data).await?;
             enforcer.reset();
             enforcer.record_sent();
         }
 
-        self.connected = true;
+        self.transport.set_connected(true);
+        self.data_seen_at = Instant::now();
+        self.resubscribed_at = Instant::now();
+        self.stale_reported = false;
         Ok(())
     }
 
+    async fn request_data(&self) -> Result<()> {
+        let msg = subscribe_message(&self.transport.app_id);
+        debug!("subscribing to data");
+        self.transport.post_logged("/Messages/RequestData", Some(&msg)).await?;
+        Ok(())
+    }
+
+    /// Watchdog for a thermostat that keeps answering polls but stops sending data.
+    async fn check_stale(&mut self) -> Result<Vec<Event>> {
+        let idle = self.data_seen_at.elapsed();
+        if idle < self.stale_after {
+            return Ok(Vec::new());
+        }
+        let mut events = Vec::new();
+        if !self.stale_reported {
+            self.stale_reported = true;
+            debug!(?idle, "no data from thermostat");
+            events.push(Event::DataStale { idle });
+            self.dispatch_events(&events);
+        }
+        if self.resubscribed_at.elapsed() >= self.stale_after {
+            debug!(?idle, "re-issuing RequestData");
+            self.resubscribed_at = Instant::now();
+            self.request_data().await?;
+        }
+        Ok(events)
+    }
+
     pub async fn poll(&mut self) -> Result<()> {
-        if !self.connected {
+        self.poll_events().await.map(|_| ())
+    }
+
+    /// One long poll; returns the events it produced.
+    async fn poll_events(&mut self) -> Result<Vec<Event>> {
+        if !self.transport.is_connected() {
             return Err(Error::NotConnected);
         }
+        let result = match self.retrieve().await.map_err(|e| self.transport.check_tls(e)) {
+            Ok(mut events) => self.check_stale().await.map(|stale| {
+                events.extend(stale);
+                events
+            }),
+            Err(e) => Err(e),
+        };
+        let status = self.last_poll_status;
+        self.set_state(|h| match (&result, status) {
+            (Ok(_), Some(502)) | (Err(_), _) => {
+                h.state = ConnectionState::Degraded;
+                h.consecutive_failures += 1;
+            }
+            (Ok(_), _) => {
+                h.state = ConnectionState::Subscribed;
+                h.consecutive_failures = 0;
+                h.last_successful_poll = Some(Utc::now());
+            }
+        });
+        result
+    }
 
-        let url = format!(
-            "{}/Messages/{}/Retrieve?LongPollingTimeout=15",
-            self.base_url, self.app_id
-        );
-        let resp = self.http.get(&url).send().await?;
+    async fn retrieve(&mut self) -> Result<Vec<Event>> {
+        let sent_at = Utc::now();
+        let started = Instant::now();
+        let fetched = self.fetch_retrieve().await;
+        let outcome = RequestOutcome {
+            sent_at,
+            latency: started.elapsed(),
+            status: self.last_poll_status,
+            error: fetched.as_ref().err().map(|e| e.to_string()),
+        };
+        self.transport.log(|logger| {
+            let body = match &fetched {
+                Ok(Some(body)) => Some(serde_json::from_str(body).unwrap_or(Value::Null)),
+                _ => None,
+            };
+            logger.log_poll(&outcome, body.as_ref());
+        });
+
+        let Some(body) = fetched? else {
+            return Ok(Vec::new());
+        };
+        let events = self.handle_body(&body)?;
+
+        if self.diag_reassert_needed {
+            self.diag_reassert_needed = false;
+            let target = self.diag_enforcer.as_ref().map(|e| e.target_level);
+            if let Some(level) = target {
+                let data = crate::protocol::set_diag_level_data(level);
+                self.transport.publish("reassert_diag_level", None, data).await?;
+                if let Some(ref mut enforcer) = self.diag_enforcer {
+                    enforcer.record_sent();
+                    if enforcer.attempts_this_hour >= DIAG_MAX_ATTEMPTS_PER_HOUR {
+                        debug!("diagLevel circuit breaker tripped, stopping reassertions for this hour");
+                    }
+                }
+            }
+        }
+
+        Ok(events)
+    }
+
+    /// One Retrieve: the body, `None` for 204 and transient 502, or the error.
+    async fn fetch_retrieve(&mut self) -> Result<Option<String>> {
+        self.last_poll_status = None;
+
+        let resp = self
+            .transport
+            .retrieve()
+            .send()
+            .await
+            .map_err(|e| self.transport.check_tls(e.into()))?;
         let status = resp.status().as_u16();
+        self.last_poll_status = Some(status);
 
         match status {
             204 => {
                 trace!("poll: no changes");
-                if let Some(ref mut logger) = self.logger {
-                    logger.log_poll(204, &Value::Null);
-                }
-                return Ok(());
+                Ok(None)
             }
             502 => {
                 debug!("poll: transient 502");
-                return Ok(());
+                Ok(None)
             }
             s if (400..600).contains(&s) => {
-                resp.error_for_status()?;
-                unreachable!();
+                let endpoint = format!("/Messages/{}/Retrieve", self.transport.app_id);
+                Err(status_error(resp, &endpoint).await)
             }
-            _ => {}
+            s if !(200..300).contains(&s) => Err(Error::Protocol(format!("unexpected Retrieve status {s}"))),
+            _ => Ok(Some(resp.text().await?)),
         }
+    }
 
-        let body = resp.text().await?;
+    /// Apply one Retrieve body: thermostat data through the diff engine,
+    /// replies through command correlation. Shared by polling and replay.
+    pub(crate) fn handle_body(&mut self, body: &str) -> Result<Vec<Event>> {
+        self.handle_retrieved(parse_retrieve(body))
+    }
 
-        if let Some(ref mut logger) = self.logger {
-            let body_json = serde_json::from_str(&body).unwrap_or(Value::Null);
-            logger.log_poll(status, &body_json);
-        }
+    /// `handle_body` for a body that's already JSON.
+    pub(crate) fn handle_value(&mut self, body: &Value) -> Result<Vec<Event>> {
+        self.handle_retrieved(parse_retrieve_value(body))
+    }
 
-        let data_payloads = parse_retrieve_response(&body);
-        if data_payloads.is_empty() {
-            return Ok(());
-        }
+    fn handle_retrieved(&mut self, retrieved: RetrieveBody) -> Result<Vec<Event>> {
+        let (thermostat, other) = match retrieved {
+            RetrieveBody::Empty => (Vec::new(), Vec::new()),
+            RetrieveBody::Messages { thermostat, other } => (thermostat, other),
+            RetrieveBody::Malformed { body, reason } => {
+                warn!(%reason, len = body.len(), "malformed poll body");
+                self.transport.update_health(|h| h.malformed_responses += 1);
+                self.dispatch_events(&[Event::MalformedResponse {
+                    body: body.clone(),
+                    reason: reason.clone(),
+                }]);
+                return Err(Error::MalformedResponse { body, reason });
+            }
+        };
 
-        for data in &data_payloads {
-            self.process_data(data);
+        if thermostat.iter().any(|m| m.data().is_some()) {
+            self.transport.update_health(|h| h.last_data = Some(Utc::now()));
+            self.data_seen_at = Instant::now();
+            self.stale_reported = false;
+        }
+        let mut events = Vec::new();
+        for msg in &thermostat {
+            if let Some(data) = msg.data() {
+                events.extend(self.process_data(data));
+            }
         }
 
-        if self.diag_reassert_needed {
-            self.diag_reassert_needed = false;
-            let target = self.diag_enforcer.as_ref().map(|e| e.target_level);
-            if let Some(level) = target {
-                let data = crate::protocol::set_diag_level_data(level);
-                self.publish_command_logged("reassert_diag_level", None, data).await?;
-                if let Some(ref mut enforcer) = self.diag_enforcer {
-                    enforcer.record_sent();
-                    if enforcer.attempts_this_hour >= DIAG_MAX_ATTEMPTS_PER_HOUR {
-                        debug!("diagLevel circuit breaker tripped, stopping reassertions for this hour");
-                    }
-                }
+        let mut replies = Vec::new();
+        for msg in &thermostat {
+            replies.extend(self.transport.correlate_reply(msg));
+        }
+        for msg in &other {
+            if let Some(reply) = self.transport.correlate_reply(msg) {
+                replies.push(reply);
+            } else if msg.sender_id.as_deref() != Some(self.transport.app_id.as_str()) {
+                debug!(sender = ?msg.sender_id, message_type = ?msg.message_type, "unexpected message");
+                self.transport.update_health(|h| h.unexpected_messages += 1);
+                replies.push(Event::UnexpectedMessage {
+                    sender_id: msg.sender_id.clone(),
+                    message_type: msg.message_type.clone(),
+                    raw: msg.raw.clone(),
+                });
             }
         }
+        self.dispatch_events(&replies);
+        events.extend(replies);
 
-        Ok(())
+        Ok(events)
     }
 
     pub async fn disconnect(&mut self) -> Result<()> {
-        let url = format!("{}/Endpoints/{}/Disconnect", self.base_url, self.app_id);
-        debug!(url = %url, "disconnecting from S30");
-        self.http.post(&url).send().await?.error_for_status()?;
-        self.connected = false;
+        let path = format!("/Endpoints/{}/Disconnect", self.transport.app_id);
+        debug!(base_url = %self.transport.base_url, path = %path, "disconnecting from S30");
+        let result = self.transport.post_logged(&path, None).await;
+        let error = result.as_ref().err().map(|e| e.to_string());
+        self.transport.log(|l| l.log_connection("disconnect", None, error.as_deref()));
+        result?;
+        self.transport.set_connected(false);
+        self.set_state(|h| h.state = ConnectionState::Disconnected);
+        Ok(())
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.transport.is_connected()
+    }
+
+    /// Forget the subscription so the next `connect()` starts a fresh one.
+    pub(crate) fn mark_disconnected(&mut self, reason: &str) {
+        self.transport.log(|l| l.log_connection("lost", None, Some(reason)));
+        self.transport.set_connected(false);
+        self.set_state(|h| h.state = ConnectionState::Reconnecting);
+    }
+
+    /// Give up on the connection after an error reconnecting won't fix.
+    pub(crate) fn mark_failed(&mut self, reason: &str) {
+        self.transport.log(|l| l.log_connection("failed", None, Some(reason)));
+        self.transport.set_connected(false);
+        self.set_state(|h| h.state = ConnectionState::Failed);
+    }
+
+    /// Fingerprint currently pinned for this thermostat, if pinning is enabled
+    /// and a certificate has been seen.
+    pub fn pinned_fingerprint(&self) -> Result<Option<String>> {
+        match &self.transport.tls {
+            Some(tls) => Ok(tls.store().load(tls.host())?),
+            None => Ok(None),
+        }
+    }
+
+    /// Forget the pinned certificate so the next connection pins whatever the
+    /// thermostat presents, e.g. after a board replacement. Verify the new
+    /// fingerprint out of band (`Error::CertificateMismatch` carries it) before
+    /// calling this.
+    pub fn repin_certificate(&self) -> Result<()> {
+        if let Some(tls) = &self.transport.tls {
+            tls.store().remove(tls.host())?;
+        }
         Ok(())
     }
 
+    pub fn connection_state(&self) -> ConnectionState {
+        self.transport.health().state
+    }
+
+    pub fn connection_health(&self) -> ConnectionHealth {
+        self.transport.health()
+    }
+
+    /// Update connection health, emitting `ConnectionChanged` on a state transition.
+    fn set_state(&mut self, f: impl FnOnce(&mut ConnectionHealth)) {
+        if let Some((from, to)) = self.transport.update_health(f) {
+            debug!(?from, ?to, "connection state changed");
+            self.dispatch_events(&[Event::ConnectionChanged { from, to }]);
+        }
+    }
+
+    /// HTTP status of the most recent Retrieve, including 502s that `poll()`
+    /// treats as success.
+    pub(crate) fn last_poll_status(&self) -> Option<u16> {
+        self.last_poll_status
+    }
+
+    pub(crate) fn reconnect_policy(&self) -> &ReconnectPolicy {
+        &self.reconnect_policy
+    }
+
+    pub(crate) fn transport(&self) -> Arc<Transport> {
+        self.transport.clone()
+    }
+
+    pub(crate) fn event_sender(&self) -> broadcast::Sender<Event> {
+        self.event_tx.clone()
+    }
+
     pub fn systems(&self) -> &[System] {
         &self.systems
     }
@@ -303,7 +762,21 @@ impl S30Client {
             .and_then(|s| s.zones.iter().find(|z| z.id == zone))
     }
 
-    fn process_data(&mut self, data: &Value) {
+    /// Stream of every event, alongside any `on_event` callbacks. Each call gets
+    /// an independent receiver. Events are only produced while something is
+    /// calling `poll()`; a subscriber that falls more than `event_buffer` events
+    /// behind receives `Event::Lagged` instead of blocking the poll loop.
+    pub fn subscribe(&self) -> impl Stream<Item = Event> + Send + 'static {
+        event_stream(self.event_tx.subscribe())
+    }
+
+    /// Receiver that always holds the latest state of every system, updated
+    /// after each poll that touched it.
+    pub fn watch_systems(&self) -> watch::Receiver<Vec<System>> {
+        self.snapshot_tx.subscribe()
+    }
+
+    fn process_data(&mut self, data: &Value) -> Vec<Event> {
         let mut all_events = Vec::new();
         let mut snapshot_system_indices = std::collections::HashSet::new();
 
@@ -550,10 +1023,15 @@ impl S30Client {
             }
         }
 
-        for event in &all_events {
-            for cb in &self.event_callbacks {
-                cb(event);
-            }
+        self.dispatch_events(&all_events);
+
+        let now = Utc::now();
+        for &sys_idx in &snapshot_system_indices {
+            self.systems[sys_idx].last_updated = Some(now);
+        }
+
+        if !snapshot_system_indices.is_empty() {
+            self.snapshot_tx.send_replace(self.systems.clone());
         }
 
         for sys_idx in snapshot_system_indices {
@@ -567,6 +1045,30 @@ impl S30Client {
         if !all_events.is_empty() {
             debug!(count = all_events.len(), "processed events from poll");
         }
+
+        all_events
+    }
+
+    fn dispatch_events(&self, events: &[Event]) {
+        for event in events {
+            for cb in &self.event_callbacks {
+                cb(event);
+            }
+            if self.event_tx.receiver_count() > 0 {
+                let _ = self.event_tx.send(event.clone());
+            }
+        }
+    }
+
+    /// MessageID of the most recently published command, from this client or any of its handles.
+    pub fn last_command_id(&self) -> Option<String> {
+        self.transport.last_command_id()
+    }
+
+    /// Latest thermostat reply referencing `message_id`, if one has arrived.
+    /// Only the last 64 commands are remembered.
+    pub fn command_outcome(&self, message_id: &str) -> Option<CommandOutcome> {
+        self.transport.command_outcome(message_id)
     }
 
     fn ensure_system(&mut self, id: &str) -> usize {
@@ -652,6 +1154,8 @@ impl S30Client {
             }
         };
 
+        zone.last_updated = Some(Utc::now());
+
         if let Some(name) = data.get("name").and_then(|v| v.as_str()) {
             zone.name = name.to_string();
         } else if let Some(name) = data.pointer("/config/name").and_then(|v| v.as_str()) {
@@ -741,6 +1245,8 @@ impl S30Client {
             }
         };
 
+        equipment.last_updated = Some(Utc::now());
+
         if let Some(et) = data.pointer("/equipment/equipType").and_then(|v| v.as_u64()) {
             equipment.equip_type = et as u16;
         }
@@ -786,85 +1292,31 @@ impl S30Client {
 
     /// Set HVAC mode for a zone. Switches to manual schedule if needed.
     pub async fn set_hvac_mode(&mut self, zone_id: u8, mode: HvacMode) -> Result<()> {
-        self.ensure_manual_schedule(zone_id).await?;
-        let manual_id = manual_schedule_id(zone_id);
-        let data = crate::protocol::set_hvac_mode_data(manual_id, mode.as_lennox_str());
-        self.publish_command_logged("set_hvac_mode", Some(zone_id), data)
-            .await
+        let plan = command::hvac_mode(&self.systems, zone_id, mode)?;
+        self.transport.publish_all(plan).await
     }
 
     /// Set heat setpoint for a zone. Enforces deadband against cool setpoint.
     pub async fn set_heat_setpoint(&mut self, zone_id: u8, temp: Temperature) -> Result<()> {
-        let zone = self.find_zone(zone_id)?;
-        let manual_id = manual_schedule_id(zone_id);
-
-        let hsp_c = temp.to_lennox_celsius();
-        let hsp_f = temp.to_lennox_fahrenheit();
-
-        let (csp_c, csp_f) = if let Some(ref c) = zone.cool_setpoint {
-            let min_cool = hsp_c + DEADBAND_C;
-            if c.to_lennox_celsius() < min_cool {
-                let adjusted = Temperature::from_celsius(min_cool);
-                (adjusted.to_lennox_celsius(), adjusted.to_lennox_fahrenheit())
-            } else {
-                (c.to_lennox_celsius(), c.to_lennox_fahrenheit())
-            }
-        } else {
-            let default_cool = Temperature::from_celsius(hsp_c + DEADBAND_C);
-            (
-                default_cool.to_lennox_celsius(),
-                default_cool.to_lennox_fahrenheit(),
-            )
-        };
-
-        self.ensure_manual_schedule(zone_id).await?;
-        let data = crate::protocol::set_setpoint_data(manual_id, hsp_f, hsp_c, csp_f, csp_c);
-        self.publish_command_logged("set_heat_setpoint", Some(zone_id), data)
-            .await
+        let plan = command::heat_setpoint(&self.systems, zone_id, temp)?;
+        self.transport.publish_all(plan).await
     }
 
     /// Set cool setpoint for a zone. Enforces deadband against heat setpoint.
     pub async fn set_cool_setpoint(&mut self, zone_id: u8, temp: Temperature) -> Result<()> {
-        let zone = self.find_zone(zone_id)?;
-        let manual_id = manual_schedule_id(zone_id);
-
-        let csp_c = temp.to_lennox_celsius();
-        let csp_f = temp.to_lennox_fahrenheit();
-
-        let (hsp_c, hsp_f) = if let Some(ref h) = zone.heat_setpoint {
-            let max_heat = csp_c - DEADBAND_C;
-            if h.to_lennox_celsius() > max_heat {
-                let adjusted = Temperature::from_celsius(max_heat);
-                (adjusted.to_lennox_celsius(), adjusted.to_lennox_fahrenheit())
-            } else {
-                (h.to_lennox_celsius(), h.to_lennox_fahrenheit())
-            }
-        } else {
-            let default_heat = Temperature::from_celsius(csp_c - DEADBAND_C);
-            (
-                default_heat.to_lennox_celsius(),
-                default_heat.to_lennox_fahrenheit(),
-            )
-        };
-
-        self.ensure_manual_schedule(zone_id).await?;
-        let data = crate::protocol::set_setpoint_data(manual_id, hsp_f, hsp_c, csp_f, csp_c);
-        self.publish_command_logged("set_cool_setpoint", Some(zone_id), data)
-            .await
+        let plan = command::cool_setpoint(&self.systems, zone_id, temp)?;
+        self.transport.publish_all(plan).await
     }
 
     /// Set system-wide away mode (occupancy override).
     pub async fn set_away(&mut self, away: bool) -> Result<()> {
-        let data = crate::protocol::set_manual_away_data(away);
-        self.publish_command_logged("set_away", None, data).await
+        self.transport.publish_all(command::away(away)).await
     }
 
     /// Set schedule hold for a zone (temporary override of current schedule period).
     pub async fn set_schedule_hold(&mut self, zone_id: u8, hold: bool) -> Result<()> {
-        self.find_zone(zone_id)?;
-        let data = crate::protocol::set_schedule_hold_data(zone_id, hold);
-        self.publish_command_logged("set_schedule_hold", Some(zone_id), data)
-            .await
+        let plan = command::schedule_hold(&self.systems, zone_id, hold)?;
+        self.transport.publish_all(plan).await
     }
 
     /// Set both heat and cool setpoints atomically. Rejects deadband violations.
@@ -874,131 +1326,227 @@ impl S30Client {
         heat: Temperature,
         cool: Temperature,
     ) -> Result<()> {
-        let hsp_c = heat.to_lennox_celsius();
-        let csp_c = cool.to_lennox_celsius();
-        if csp_c < hsp_c + DEADBAND_C {
-            return Err(Error::InvalidSetpoints {
-                heat_c: hsp_c,
-                cool_c: csp_c,
-                deadband_c: DEADBAND_C,
-            });
-        }
-        self.find_zone(zone_id)?;
-        self.ensure_manual_schedule(zone_id).await?;
-        let manual_id = manual_schedule_id(zone_id);
-        let data = crate::protocol::set_setpoint_data(
-            manual_id,
-            heat.to_lennox_fahrenheit(),
-            hsp_c,
-            cool.to_lennox_fahrenheit(),
-            csp_c,
-        );
-        self.publish_command_logged("set_setpoints", Some(zone_id), data)
-            .await
+        let plan = command::setpoints(&self.systems, zone_id, heat, cool)?;
+        self.transport.publish_all(plan).await
     }
 
     /// Set fan mode for a zone. Switches to manual schedule if needed.
     pub async fn set_fan_mode(&mut self, zone_id: u8, mode: FanMode) -> Result<()> {
-        self.ensure_manual_schedule(zone_id).await?;
-        let manual_id = manual_schedule_id(zone_id);
-        let data = crate::protocol::set_fan_mode_data(manual_id, mode.as_lennox_str());
-        self.publish_command_logged("set_fan_mode", Some(zone_id), data)
+        let plan = command::fan_mode(&self.systems, zone_id, mode)?;
+        self.transport.publish_all(plan).await
+    }
+
+    // -- Confirmed command methods --
+    //
+    // Each sends the command, then polls until the reported state reflects it
+    // (`Confirmed`), the deadline passes after the thermostat reported the
+    // affected fields with other values or replied with an error (`Rejected`),
+    // or nothing relevant arrived at all (`TimedOut`). A long poll still
+    // running at the deadline is cut short.
+
+    pub async fn set_hvac_mode_and_confirm(
+        &mut self,
+        zone_id: u8,
+        mode: HvacMode,
+        timeout: Duration,
+    ) -> Result<Confirmation> {
+        let mark = self.transport.command_seq();
+        self.set_hvac_mode(zone_id, mode).await?;
+        self.await_expectation(Expectation::zone_mode(zone_id, mode), mark, timeout)
             .await
     }
 
-    /// Set an equipment parameter value. Validates against descriptor before sending.
-    pub async fn set_equipment_parameter(
+    pub async fn set_heat_setpoint_and_confirm(
         &mut self,
-        equipment_id: u16,
-        pid: u16,
-        value: &str,
-    ) -> Result<()> {
-        let equipment = self.systems.iter()
-            .flat_map(|s| &s.equipments)
-            .find(|e| e.id == equipment_id)
-            .ok_or_else(|| Error::InvalidParameter {
-                equipment_id,
-                pid,
-                reason: "equipment not found".to_string(),
-            })?;
+        zone_id: u8,
+        temp: Temperature,
+        timeout: Duration,
+    ) -> Result<Confirmation> {
+        let mark = self.transport.command_seq();
+        self.set_heat_setpoint(zone_id, temp).await?;
+        let expect = Expectation::zone_setpoints(zone_id, Some(temp.to_lennox_celsius()), None);
+        self.await_expectation(expect, mark, timeout).await
+    }
 
-        let equip_type = equipment.equip_type;
+    pub async fn set_cool_setpoint_and_confirm(
+        &mut self,
+        zone_id: u8,
+        temp: Temperature,
+        timeout: Duration,
+    ) -> Result<Confirmation> {
+        let mark = self.transport.command_seq();
+        self.set_cool_setpoint(zone_id, temp).await?;
+        let expect = Expectation::zone_setpoints(zone_id, None, Some(temp.to_lennox_celsius()));
+        self.await_expectation(expect, mark, timeout).await
+    }
 
-        let param = equipment.parameters.get(&pid)
-            .ok_or_else(|| Error::InvalidParameter {
-                equipment_id,
-                pid,
-                reason: "parameter not found".to_string(),
-            })?;
+    pub async fn set_setpoints_and_confirm(
+        &mut self,
+        zone_id: u8,
+        heat: Temperature,
+        cool: Temperature,
+        timeout: Duration,
+    ) -> Result<Confirmation> {
+        let mark = self.transport.command_seq();
+        self.set_setpoints(zone_id, heat, cool).await?;
+        let expect = Expectation::zone_setpoints(
+            zone_id,
+            Some(heat.to_lennox_celsius()),
+            Some(cool.to_lennox_celsius()),
+        );
+        self.await_expectation(expect, mark, timeout).await
+    }
 
-        if !param.enabled {
-            return Err(Error::InvalidParameter {
-                equipment_id,
-                pid,
-                reason: "parameter is read-only (enabled=false)".to_string(),
-            });
-        }
+    pub async fn set_fan_mode_and_confirm(
+        &mut self,
+        zone_id: u8,
+        mode: FanMode,
+        timeout: Duration,
+    ) -> Result<Confirmation> {
+        let mark = self.transport.command_seq();
+        self.set_fan_mode(zone_id, mode).await?;
+        self.await_expectation(Expectation::zone_fan_mode(zone_id, mode), mark, timeout)
+            .await
+    }
 
-        let validated = validate_parameter(param, value).map_err(|reason| {
-            Error::InvalidParameter { equipment_id, pid, reason }
-        })?;
+    pub async fn set_away_and_confirm(&mut self, away: bool, timeout: Duration) -> Result<Confirmation> {
+        let mark = self.transport.command_seq();
+        self.set_away(away).await?;
+        self.await_expectation(Expectation::manual_away(away), mark, timeout)
+            .await
+    }
 
-        let data = crate::protocol::set_parameter_data(equip_type, pid, &validated);
-        self.publish_command_logged("set_parameter", None, data).await
+    pub async fn set_schedule_hold_and_confirm(
+        &mut self,
+        zone_id: u8,
+        hold: bool,
+        timeout: Duration,
+    ) -> Result<Confirmation> {
+        let mark = self.transport.command_seq();
+        self.set_schedule_hold(zone_id, hold).await?;
+        self.await_expectation(Expectation::schedule_hold(zone_id, hold), mark, timeout)
+            .await
     }
 
-    pub async fn set_diag_level(&mut self, level: u8) -> Result<()> {
-        let data = crate::protocol::set_diag_level_data(level);
-        self.publish_command_logged("set_diag_level", None, data).await
+    /// Set an equipment parameter value. Validates against descriptor before sending.
+    pub async fn set_equipment_parameter(
+        &mut self,
+        equipment_id: u16,
+        pid: u16,
+        value: &str,
+    ) -> Result<()> {
+        let plan = command::equipment_parameter(&self.systems, equipment_id, pid, value)?;
+        self.transport.publish_all(plan).await
     }
 
-    // -- Helpers --
+    /// Set an equipment parameter and wait for the thermostat to report it back.
+    ///
+    /// Polls until a poll reports this equipment's parameter. Returns the confirmed
+    /// value, `Error::ParameterRejected` if the thermostat reports any other value
+    /// (rejected, reverted, or the old value re-reported), or
+    /// `Error::ParameterUnconfirmed` once `timeout` has elapsed, cutting short a
+    /// long poll in progress. The write may still apply after that, so don't
+    /// retry it blindly.
+    pub async fn set_equipment_parameter_confirmed(
+        &mut self,
+        equipment_id: u16,
+        pid: u16,
+        value: &str,
+        timeout: Duration,
+    ) -> Result<String> {
+        let (equip_type, requested) =
+            command::prepare_parameter(&self.systems, equipment_id, pid, value)?;
+        let unchanged = self
+            .reported_parameter(equipment_id, pid)
+            .is_some_and(|(_, value)| value == requested);
+
+        let data = crate::protocol::set_parameter_data(equip_type, pid, &requested);
+        self.transport.publish("set_parameter", None, data).await?;
+
+        // Already at the requested value: the thermostat has nothing to report.
+        if unchanged {
+            return Ok(requested);
+        }
 
-    fn find_zone(&self, zone_id: u8) -> Result<&Zone> {
-        for system in &self.systems {
-            for zone in &system.zones {
-                if zone.id == zone_id {
-                    return Ok(zone);
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline {
+            let before = self.reported_parameter(equipment_id, pid).and_then(|(at, _)| at);
+            let Ok(events) = tokio::time::timeout_at(deadline.into(), self.poll_events()).await else {
+                break;
+            };
+            events?;
+            // Any report of the equipment after the write settles it, even one
+            // that repeats the old value.
+            if let Some((at, actual)) = self.reported_parameter(equipment_id, pid)
+                && at != before
+            {
+                if actual == requested {
+                    return Ok(actual);
                 }
+                return Err(Error::ParameterRejected {
+                    equipment_id,
+                    pid,
+                    requested,
+                    actual,
+                });
             }
         }
-        Err(Error::InvalidZone(zone_id))
+        Err(Error::ParameterUnconfirmed {
+            equipment_id,
+            pid,
+            requested,
+        })
     }
 
-    async fn ensure_manual_schedule(&mut self, zone_id: u8) -> Result<()> {
-        let schedule_id = self.find_zone(zone_id)?.schedule_id;
-        let manual_id = manual_schedule_id(zone_id);
-        if schedule_id != Some(manual_id) {
-            let data = crate::protocol::set_manual_mode_data(zone_id);
-            self.publish_command_logged("set_manual_schedule", Some(zone_id), data)
-                .await?;
-        }
-        Ok(())
+    /// When the equipment was last reported, and the value it reports for `pid`.
+    fn reported_parameter(&self, equipment_id: u16, pid: u16) -> Option<(Option<DateTime<Utc>>, String)> {
+        self.systems
+            .iter()
+            .find_map(|s| s.equipment(equipment_id))
+            .and_then(|e| Some((e.last_updated, e.parameter(pid)?.value.clone())))
     }
 
-    async fn publish_command_logged(
+    /// Set a catalogued parameter from a typed value, e.g. a `Temperature` for a
+    /// balance point. Converts to the parameter's unit, then validates and sends
+    /// like `set_equipment_parameter`.
+    pub async fn set_parameter_typed<T: ParamValue>(
         &mut self,
-        action: &str,
-        zone: Option<u8>,
-        data: serde_json::Value,
+        equipment_id: u16,
+        key: ParamKey,
+        value: T,
     ) -> Result<()> {
-        if !self.connected {
-            return Err(Error::NotConnected);
-        }
+        let plan = command::parameter_typed(&self.systems, equipment_id, key, value)?;
+        self.transport.publish_all(plan).await
+    }
 
-        if let Some(ref mut logger) = self.logger {
-            logger.log_command(action, zone, &data);
-        }
+    pub async fn set_diag_level(&mut self, level: u8) -> Result<()> {
+        self.transport.publish_all(command::diag_level(level)).await
+    }
 
-        let msg = crate::protocol::command_message(&self.app_id, data);
-        let url = format!("{}/Messages/Publish", self.base_url);
-        self.http
-            .post(&url)
-            .json(&msg)
-            .send()
-            .await?
-            .error_for_status()?;
-        Ok(())
+    // -- Helpers --
+
+    /// `mark` is the command sequence number before the request was sent; error
+    /// replies to anything published after it reject the request.
+    async fn await_expectation(
+        &mut self,
+        expect: Expectation,
+        mark: u64,
+        timeout: Duration,
+    ) -> Result<Confirmation> {
+        let mut expect = expect.with_commands(self.transport.command_ids_since(mark));
+        if expect.is_met(&self.systems) {
+            return Ok(Confirmation::Confirmed);
+        }
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline {
+            let Ok(events) = tokio::time::timeout_at(deadline.into(), self.poll_events()).await else {
+                break;
+            };
+            if let Some(confirmation) = expect.observe(&self.systems, &events?) {
+                return Ok(confirmation);
+            }
+        }
+        Ok(expect.finish())
     }
 }
 
@@ -1022,7 +1570,60 @@ fn merge_json(target: &mut Map<String, Value>, key: &str, new_data: &Value) {
     deep_merge(entry, new_data);
 }
 
-fn validate_parameter(param: &Parameter, value: &str) -> std::result::Result<String, String> {
+/// Check `host` is a bare host or host:port and return the base URL.
+fn validate_host(protocol: Protocol, host: &str) -> Result<String> {
+    let invalid = |reason: &str| Error::InvalidHost {
+        host: host.to_string(),
+        reason: reason.to_string(),
+    };
+    if host.is_empty() {
+        return Err(invalid("empty"));
+    }
+    if host.contains(['/', '?', '#', '@']) || host.chars().any(char::is_whitespace) {
+        return Err(invalid("expected host or host:port"));
+    }
+    let base_url = format!("{protocol}://{host}");
+    let url = reqwest::Url::parse(&base_url).map_err(|e| invalid(&e.to_string()))?;
+    if url.host_str().is_none_or(str::is_empty) {
+        return Err(invalid("missing host"));
+    }
+    if url.port() == Some(0) {
+        return Err(invalid("port must be 1-65535"));
+    }
+    Ok(base_url)
+}
+
+/// app_ids appear in URL paths and name the thermostat's queue for this client.
+fn validate_reconnect_policy(policy: &ReconnectPolicy) -> Result<()> {
+    if policy.multiplier.is_nan() || policy.multiplier < 1.0 {
+        return Err(Error::InvalidReconnectPolicy(format!(
+            "multiplier must be at least 1.0, got {}",
+            policy.multiplier
+        )));
+    }
+    Ok(())
+}
+
+fn validate_app_id(app_id: String) -> Result<String> {
+    let reason = if app_id.is_empty() {
+        Some("empty".to_string())
+    } else if app_id.len() > MAX_APP_ID_LEN {
+        Some(format!("longer than {MAX_APP_ID_LEN} characters"))
+    } else if !app_id
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        Some("only letters, digits, '_' and '-' are allowed".to_string())
+    } else {
+        None
+    };
+    match reason {
+        Some(reason) => Err(Error::InvalidAppId { app_id, reason }),
+        None => Ok(app_id),
+    }
+}
+
+pub(crate) fn validate_parameter(param: &Parameter, value: &str) -> std::result::Result<String, String> {
     match &param.descriptor {
         Descriptor::Range { min, max, inc, .. } => {
             let v: f64 = value.parse().map_err(|_| format!("not a number: {value}"))?;
@@ -1093,6 +1694,7 @@ fn parse_descriptor(param_data: &Value) -> Descriptor {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::command::DEADBAND_C;
 
     #[test]
     fn deadband_enforced_on_heat_setpoint() {
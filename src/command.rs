@@ -0,0 +1,253 @@
+// Command planning: turns a request plus the current state into the messages
+// to publish. Pure over `&[System]`, so the poller and any `S30Handle` build
+// identical commands from whichever snapshot they hold.
+
+use serde_json::Value;
+
+use crate::client::validate_parameter;
+use crate::params::{effective_unit, ParamKey, ParamValue};
+use crate::protocol::manual_schedule_id;
+use crate::types::*;
+use crate::{Error, Result};
+
+pub(crate) const DEADBAND_C: f64 = 1.5;
+
+/// One message to publish.
+pub(crate) struct Publish {
+    pub action: &'static str,
+    pub zone: Option<u8>,
+    pub data: Value,
+}
+
+impl Publish {
+    fn new(action: &'static str, zone: Option<u8>, data: Value) -> Self {
+        Self { action, zone, data }
+    }
+}
+
+fn find_zone(systems: &[System], zone_id: u8) -> Result<&Zone> {
+    systems
+        .iter()
+        .flat_map(|s| &s.zones)
+        .find(|z| z.id == zone_id)
+        .ok_or(Error::InvalidZone(zone_id))
+}
+
+/// Switch the zone to its manual schedule first if it isn't already on it.
+fn manual_schedule(systems: &[System], zone_id: u8, plan: &mut Vec<Publish>) -> Result<()> {
+    let schedule_id = find_zone(systems, zone_id)?.schedule_id;
+    if schedule_id != Some(manual_schedule_id(zone_id)) {
+        let data = crate::protocol::set_manual_mode_data(zone_id);
+        plan.push(Publish::new("set_manual_schedule", Some(zone_id), data));
+    }
+    Ok(())
+}
+
+pub(crate) fn hvac_mode(systems: &[System], zone_id: u8, mode: HvacMode) -> Result<Vec<Publish>> {
+    let mut plan = Vec::new();
+    manual_schedule(systems, zone_id, &mut plan)?;
+    let data = crate::protocol::set_hvac_mode_data(manual_schedule_id(zone_id), mode.as_lennox_str());
+    plan.push(Publish::new("set_hvac_mode", Some(zone_id), data));
+    Ok(plan)
+}
+
+/// Heat setpoint, pushing the cool setpoint up if it would violate the deadband.
+pub(crate) fn heat_setpoint(systems: &[System], zone_id: u8, temp: Temperature) -> Result<Vec<Publish>> {
+    let zone = find_zone(systems, zone_id)?;
+
+    let hsp_c = temp.to_lennox_celsius();
+    let hsp_f = temp.to_lennox_fahrenheit();
+
+    let (csp_c, csp_f) = if let Some(ref c) = zone.cool_setpoint {
+        let min_cool = hsp_c + DEADBAND_C;
+        if c.to_lennox_celsius() < min_cool {
+            let adjusted = Temperature::from_celsius(min_cool);
+            (adjusted.to_lennox_celsius(), adjusted.to_lennox_fahrenheit())
+        } else {
+            (c.to_lennox_celsius(), c.to_lennox_fahrenheit())
+        }
+    } else {
+        let default_cool = Temperature::from_celsius(hsp_c + DEADBAND_C);
+        (
+            default_cool.to_lennox_celsius(),
+            default_cool.to_lennox_fahrenheit(),
+        )
+    };
+
+    let mut plan = Vec::new();
+    manual_schedule(systems, zone_id, &mut plan)?;
+    let data = crate::protocol::set_setpoint_data(manual_schedule_id(zone_id), hsp_f, hsp_c, csp_f, csp_c);
+    plan.push(Publish::new("set_heat_setpoint", Some(zone_id), data));
+    Ok(plan)
+}
+
+/// Cool setpoint, pulling the heat setpoint down if it would violate the deadband.
+pub(crate) fn cool_setpoint(systems: &[System], zone_id: u8, temp: Temperature) -> Result<Vec<Publish>> {
+    let zone = find_zone(systems, zone_id)?;
+
+    let csp_c = temp.to_lennox_celsius();
+    let csp_f = temp.to_lennox_fahrenheit();
+
+    let (hsp_c, hsp_f) = if let Some(ref h) = zone.heat_setpoint {
+        let max_heat = csp_c - DEADBAND_C;
+        if h.to_lennox_celsius() > max_heat {
+            let adjusted = Temperature::from_celsius(max_heat);
+            (adjusted.to_lennox_celsius(), adjusted.to_lennox_fahrenheit())
+        } else {
+            (h.to_lennox_celsius(), h.to_lennox_fahrenheit())
+        }
+    } else {
+        let default_heat = Temperature::from_celsius(csp_c - DEADBAND_C);
+        (
+            default_heat.to_lennox_celsius(),
+            default_heat.to_lennox_fahrenheit(),
+        )
+    };
+
+    let mut plan = Vec::new();
+    manual_schedule(systems, zone_id, &mut plan)?;
+    let data = crate::protocol::set_setpoint_data(manual_schedule_id(zone_id), hsp_f, hsp_c, csp_f, csp_c);
+    plan.push(Publish::new("set_cool_setpoint", Some(zone_id), data));
+    Ok(plan)
+}
+
+/// Both setpoints at once. Rejects deadband violations instead of adjusting.
+pub(crate) fn setpoints(
+    systems: &[System],
+    zone_id: u8,
+    heat: Temperature,
+    cool: Temperature,
+) -> Result<Vec<Publish>> {
+    let hsp_c = heat.to_lennox_celsius();
+    let csp_c = cool.to_lennox_celsius();
+    if csp_c < hsp_c + DEADBAND_C {
+        return Err(Error::InvalidSetpoints {
+            heat_c: hsp_c,
+            cool_c: csp_c,
+            deadband_c: DEADBAND_C,
+        });
+    }
+    let mut plan = Vec::new();
+    manual_schedule(systems, zone_id, &mut plan)?;
+    let data = crate::protocol::set_setpoint_data(
+        manual_schedule_id(zone_id),
+        heat.to_lennox_fahrenheit(),
+        hsp_c,
+        cool.to_lennox_fahrenheit(),
+        csp_c,
+    );
+    plan.push(Publish::new("set_setpoints", Some(zone_id), data));
+    Ok(plan)
+}
+
+pub(crate) fn fan_mode(systems: &[System], zone_id: u8, mode: FanMode) -> Result<Vec<Publish>> {
+    let mut plan = Vec::new();
+    manual_schedule(systems, zone_id, &mut plan)?;
+    let data = crate::protocol::set_fan_mode_data(manual_schedule_id(zone_id), mode.as_lennox_str());
+    plan.push(Publish::new("set_fan_mode", Some(zone_id), data));
+    Ok(plan)
+}
+
+pub(crate) fn away(away: bool) -> Vec<Publish> {
+    let data = crate::protocol::set_manual_away_data(away);
+    vec![Publish::new("set_away", None, data)]
+}
+
+pub(crate) fn schedule_hold(systems: &[System], zone_id: u8, hold: bool) -> Result<Vec<Publish>> {
+    find_zone(systems, zone_id)?;
+    let data = crate::protocol::set_schedule_hold_data(zone_id, hold);
+    Ok(vec![Publish::new("set_schedule_hold", Some(zone_id), data)])
+}
+
+pub(crate) fn diag_level(level: u8) -> Vec<Publish> {
+    let data = crate::protocol::set_diag_level_data(level);
+    vec![Publish::new("set_diag_level", None, data)]
+}
+
+/// Look up and validate a parameter write. Returns the equipment type and
+/// the value as the thermostat expects it (radio labels mapped to ids).
+pub(crate) fn prepare_parameter(
+    systems: &[System],
+    equipment_id: u16,
+    pid: u16,
+    value: &str,
+) -> Result<(u16, String)> {
+    let equipment = find_equipment(systems, equipment_id, pid)?;
+
+    let param = equipment.parameters.get(&pid)
+        .ok_or_else(|| Error::InvalidParameter {
+            equipment_id,
+            pid,
+            reason: "parameter not found".to_string(),
+        })?;
+
+    if !param.enabled {
+        return Err(Error::InvalidParameter {
+            equipment_id,
+            pid,
+            reason: "parameter is read-only (enabled=false)".to_string(),
+        });
+    }
+
+    let validated = validate_parameter(param, value).map_err(|reason| {
+        Error::InvalidParameter { equipment_id, pid, reason }
+    })?;
+
+    Ok((equipment.equip_type, validated))
+}
+
+pub(crate) fn equipment_parameter(
+    systems: &[System],
+    equipment_id: u16,
+    pid: u16,
+    value: &str,
+) -> Result<Vec<Publish>> {
+    let (equip_type, validated) = prepare_parameter(systems, equipment_id, pid, value)?;
+    let data = crate::protocol::set_parameter_data(equip_type, pid, &validated);
+    Ok(vec![Publish::new("set_parameter", None, data)])
+}
+
+/// Convert a typed value to the catalogued parameter's unit, then validate
+/// like `equipment_parameter`.
+pub(crate) fn parameter_typed<T: ParamValue>(
+    systems: &[System],
+    equipment_id: u16,
+    key: ParamKey,
+    value: T,
+) -> Result<Vec<Publish>> {
+    let pid = key.pid;
+    let equipment = find_equipment(systems, equipment_id, pid)?;
+
+    if equipment.equip_type != key.equip_type {
+        return Err(Error::InvalidParameter {
+            equipment_id,
+            pid,
+            reason: format!(
+                "equipment type {} does not match parameter key type {}",
+                equipment.equip_type, key.equip_type
+            ),
+        });
+    }
+
+    let param = equipment.parameters.get(&pid)
+        .ok_or_else(|| Error::InvalidParameter {
+            equipment_id,
+            pid,
+            reason: "parameter not found".to_string(),
+        })?;
+
+    let raw = value.to_parameter(effective_unit(param, key));
+    equipment_parameter(systems, equipment_id, pid, &raw)
+}
+
+fn find_equipment(systems: &[System], equipment_id: u16, pid: u16) -> Result<&Equipment> {
+    systems
+        .iter()
+        .flat_map(|s| &s.equipments)
+        .find(|e| e.id == equipment_id)
+        .ok_or_else(|| Error::InvalidParameter {
+            equipment_id,
+            pid,
+            reason: "equipment not found".to_string(),
+        })
+}
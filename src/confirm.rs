@@ -0,0 +1,211 @@
+use crate::types::*;
+
+/// Outcome of waiting for a command to take effect.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Confirmation {
+    /// Reported state reflects the request.
+    Confirmed,
+    /// The thermostat replied with an error, or reported the affected fields
+    /// but not with the requested values.
+    Rejected,
+    /// Nothing relevant was reported before the deadline.
+    TimedOut,
+}
+
+type StatePredicate = Box<dyn Fn(&[System]) -> bool + Send + Sync>;
+type EventPredicate = Box<dyn Fn(&Event) -> bool + Send + Sync>;
+
+/// What a command should look like once applied, checked against each poll.
+pub(crate) struct Expectation {
+    matches: StatePredicate,
+    relevant: EventPredicate,
+    saw_relevant: bool,
+    commands: Vec<String>,
+}
+
+fn find_zone(systems: &[System], zone_id: u8) -> Option<&Zone> {
+    systems.iter().flat_map(|s| &s.zones).find(|z| z.id == zone_id)
+}
+
+fn same_setpoint(actual: Option<Temperature>, wanted: Option<f64>) -> bool {
+    match (actual, wanted) {
+        (_, None) => true,
+        (Some(t), Some(c)) => (t.to_lennox_celsius() - c).abs() < 0.01,
+        (None, Some(_)) => false,
+    }
+}
+
+impl Expectation {
+    fn new(
+        matches: impl Fn(&[System]) -> bool + Send + Sync + 'static,
+        relevant: impl Fn(&Event) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            matches: Box::new(matches),
+            relevant: Box::new(relevant),
+            saw_relevant: false,
+            commands: Vec::new(),
+        }
+    }
+
+    /// MessageIDs of the commands sent for this request. An error reply to any
+    /// of them rejects the request immediately.
+    pub fn with_commands(mut self, message_ids: Vec<String>) -> Self {
+        self.commands = message_ids;
+        self
+    }
+
+    pub fn zone_mode(zone_id: u8, mode: HvacMode) -> Self {
+        Self::new(
+            move |systems| find_zone(systems, zone_id).is_some_and(|z| z.mode == Some(mode)),
+            move |e| matches!(e, Event::ZoneModeChanged { zone_id: id, .. } if *id == zone_id),
+        )
+    }
+
+    /// Setpoints in Lennox-rounded Celsius; `None` leaves that side unchecked.
+    pub fn zone_setpoints(zone_id: u8, heat_c: Option<f64>, cool_c: Option<f64>) -> Self {
+        Self::new(
+            move |systems| {
+                find_zone(systems, zone_id).is_some_and(|z| {
+                    same_setpoint(z.heat_setpoint, heat_c) && same_setpoint(z.cool_setpoint, cool_c)
+                })
+            },
+            move |e| matches!(e, Event::ZoneSetpointsChanged { zone_id: id, .. } if *id == zone_id),
+        )
+    }
+
+    pub fn zone_fan_mode(zone_id: u8, mode: FanMode) -> Self {
+        Self::new(
+            move |systems| find_zone(systems, zone_id).is_some_and(|z| z.fan_mode == Some(mode)),
+            move |e| matches!(e, Event::ZoneFanChanged { zone_id: id, .. } if *id == zone_id),
+        )
+    }
+
+    pub fn schedule_hold(zone_id: u8, hold: bool) -> Self {
+        Self::new(
+            move |systems| find_zone(systems, zone_id).is_some_and(|z| z.override_active == hold),
+            move |e| matches!(e, Event::ZoneHoldChanged { zone_id: id, .. } if *id == zone_id),
+        )
+    }
+
+    pub fn manual_away(away: bool) -> Self {
+        Self::new(
+            move |systems| systems.first().is_some_and(|s| s.manual_away == away),
+            |e| matches!(e, Event::AwayModeChanged { .. }),
+        )
+    }
+
+    pub fn is_met(&self, systems: &[System]) -> bool {
+        (self.matches)(systems)
+    }
+
+    /// Feed one poll's worth of state and events. Returns `Confirmed` as soon as
+    /// state matches; otherwise remembers whether anything relevant was reported.
+    pub fn observe(&mut self, systems: &[System], events: &[Event]) -> Option<Confirmation> {
+        if self.is_met(systems) {
+            return Some(Confirmation::Confirmed);
+        }
+        let error_reply = events.iter().any(|e| {
+            matches!(e, Event::CommandOutcome { outcome }
+                if outcome.is_error() && self.commands.contains(&outcome.message_id))
+        });
+        if error_reply {
+            return Some(Confirmation::Rejected);
+        }
+        if events.iter().any(|e| (self.relevant)(e)) {
+            self.saw_relevant = true;
+        }
+        None
+    }
+
+    /// Verdict once the deadline has passed without confirmation.
+    pub fn finish(&self) -> Confirmation {
+        if self.saw_relevant {
+            Confirmation::Rejected
+        } else {
+            Confirmation::TimedOut
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn system_with_zone(zone: Zone) -> Vec<System> {
+        vec![System {
+            zones: vec![zone],
+            ..Default::default()
+        }]
+    }
+
+    #[test]
+    fn confirms_when_state_matches() {
+        let mut expect = Expectation::zone_mode(0, HvacMode::Cool);
+        let systems = system_with_zone(Zone {
+            id: 0,
+            mode: Some(HvacMode::Cool),
+            ..Default::default()
+        });
+        assert_eq!(expect.observe(&systems, &[]), Some(Confirmation::Confirmed));
+    }
+
+    #[test]
+    fn relevant_update_without_match_is_rejected() {
+        let mut expect = Expectation::zone_mode(0, HvacMode::Cool);
+        let systems = system_with_zone(Zone {
+            id: 0,
+            mode: Some(HvacMode::Heat),
+            ..Default::default()
+        });
+        let events = [Event::ZoneModeChanged {
+            zone_id: 0,
+            name: String::new(),
+            mode: HvacMode::Heat,
+        }];
+        assert_eq!(expect.observe(&systems, &events), None);
+        assert_eq!(expect.finish(), Confirmation::Rejected);
+    }
+
+    #[test]
+    fn unrelated_updates_time_out() {
+        let mut expect = Expectation::zone_mode(0, HvacMode::Cool);
+        let systems = system_with_zone(Zone {
+            id: 0,
+            ..Default::default()
+        });
+        let events = [Event::ZoneModeChanged {
+            zone_id: 1,
+            name: String::new(),
+            mode: HvacMode::Heat,
+        }];
+        assert_eq!(expect.observe(&systems, &events), None);
+        assert_eq!(expect.finish(), Confirmation::TimedOut);
+    }
+
+    #[test]
+    fn error_reply_rejects_immediately() {
+        let mut expect = Expectation::manual_away(true).with_commands(vec!["cmd-1".to_string()]);
+        let outcome = CommandOutcome {
+            message_id: "cmd-1".to_string(),
+            action: "set_away".to_string(),
+            zone: None,
+            sender_id: Some("LCC".to_string()),
+            message_type: Some("CommandError".to_string()),
+            reply: serde_json::json!({}),
+        };
+        let events = [Event::CommandOutcome { outcome }];
+        assert_eq!(expect.observe(&[], &events), Some(Confirmation::Rejected));
+    }
+
+    #[test]
+    fn setpoints_compare_lennox_rounded() {
+        let expect = Expectation::zone_setpoints(0, Some(21.0), None);
+        let systems = system_with_zone(Zone {
+            id: 0,
+            heat_setpoint: Some(Temperature::from_celsius(21.1)),
+            ..Default::default()
+        });
+        assert!(expect.is_met(&systems));
+    }
+}
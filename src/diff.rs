@@ -65,6 +65,28 @@ pub(crate) fn diff_json(
     }
 }
 
+/// Deep-merge `patch` into `target`. Arrays of objects with an `id` merge
+/// element by element, as the thermostat's partial updates do; any other
+/// array replaces the old one.
+pub(crate) fn merge_by_id(target: &mut Value, patch: &Value) {
+    match (target, patch) {
+        (Value::Object(t), Value::Object(p)) => {
+            for (k, v) in p {
+                merge_by_id(t.entry(k.clone()).or_insert(Value::Null), v);
+            }
+        }
+        (Value::Array(t), Value::Array(p)) if p.iter().all(|v| v.get("id").is_some()) => {
+            for item in p {
+                match t.iter_mut().find(|e| e.get("id") == item.get("id")) {
+                    Some(existing) => merge_by_id(existing, item),
+                    None => t.push(item.clone()),
+                }
+            }
+        }
+        (t, p) => *t = p.clone(),
+    }
+}
+
 fn try_build_temperature(f_field: &str, parent: &Value) -> Option<Temperature> {
     let c_field = celsius_companion(f_field)?;
     let f_val = parent.get(f_field)?.as_f64()?;
@@ -231,6 +253,18 @@ mod tests {
     use super::*;
     use serde_json::json;
 
+    #[test]
+    fn merge_by_id_merges_arrays_by_id() {
+        let mut target = json!({"zones": [{"id": 0, "a": 1}, {"id": 1, "a": 1}]});
+        merge_by_id(&mut target, &json!({"zones": [{"id": 1, "a": 2, "b": 3}, {"id": 2}]}));
+        assert_eq!(
+            target,
+            json!({"zones": [{"id": 0, "a": 1}, {"id": 1, "a": 2, "b": 3}, {"id": 2}]})
+        );
+        merge_by_id(&mut target, &json!({"zones": [1, 2]}));
+        assert_eq!(target, json!({"zones": [1, 2]}));
+    }
+
     #[test]
     fn diff_detects_leaf_change() {
         let prev = json!({"status": {"temperature": 71.0}});
@@ -0,0 +1,344 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::ops::ControlFlow;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::{broadcast, mpsc, oneshot, watch};
+use tokio_stream::Stream;
+use tracing::{debug, error, warn};
+
+use crate::client::{event_stream, S30Client};
+use crate::command;
+use crate::params::{ParamKey, ParamValue};
+use crate::transport::Transport;
+use crate::types::*;
+use crate::{Error, Result};
+
+/// Consecutive 502s tolerated before the driver re-subscribes.
+const MAX_CONSECUTIVE_502: u32 = 5;
+
+/// Delays between reconnect attempts: exponential growth with jitter.
+#[derive(Debug, Clone)]
+pub struct ReconnectPolicy {
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    /// Growth per attempt; at least 1.0.
+    pub multiplier: f64,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            multiplier: 2.0,
+        }
+    }
+}
+
+struct Backoff {
+    policy: ReconnectPolicy,
+    attempt: u32,
+}
+
+impl Backoff {
+    fn new(policy: ReconnectPolicy) -> Self {
+        Self { policy, attempt: 0 }
+    }
+
+    /// Next delay: half the exponential step plus a random share of the other half,
+    /// so several clients restarting together don't retry in lockstep.
+    fn next_delay(&mut self) -> Duration {
+        let max = self.policy.max_delay;
+        // In f64 and capped before converting, so a huge step can't overflow.
+        let exp = self.policy.multiplier.powi(self.attempt.min(32) as i32);
+        let secs = (self.policy.initial_delay.as_secs_f64() * exp).min(max.as_secs_f64());
+        let step = Duration::try_from_secs_f64(secs).map_or(max, |d| d.min(max));
+        self.attempt = self.attempt.saturating_add(1);
+        step / 2 + (step / 2).mul_f64(jitter())
+    }
+
+    fn reset(&mut self) {
+        self.attempt = 0;
+    }
+}
+
+/// Uniform-ish value in [0, 1) from std's randomly keyed hasher.
+fn jitter() -> f64 {
+    let bits = RandomState::new().build_hasher().finish();
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Cloneable, `Send + Sync` handle to a client.
+///
+/// Reads come from the latest snapshot published by the poller; commands are
+/// validated against that snapshot and published directly, so they go out
+/// immediately even while the poller is blocked in a long poll.
+#[derive(Clone)]
+pub struct S30Handle {
+    transport: Arc<Transport>,
+    systems: watch::Receiver<Vec<System>>,
+    events: broadcast::Sender<Event>,
+    driver: Option<mpsc::Sender<oneshot::Sender<()>>>,
+}
+
+impl S30Handle {
+    /// Clone of the latest state of every system.
+    pub fn systems(&self) -> Vec<System> {
+        self.systems.borrow().clone()
+    }
+
+    pub fn zone(&self, system: usize, zone: u8) -> Option<Zone> {
+        self.systems
+            .borrow()
+            .get(system)
+            .and_then(|s| s.zones.iter().find(|z| z.id == zone))
+            .cloned()
+    }
+
+    pub fn watch_systems(&self) -> watch::Receiver<Vec<System>> {
+        self.systems.clone()
+    }
+
+    pub fn subscribe(&self) -> impl Stream<Item = Event> + Send + 'static {
+        event_stream(self.events.subscribe())
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.transport.is_connected()
+    }
+
+    pub fn connection_state(&self) -> ConnectionState {
+        self.transport.health().state
+    }
+
+    pub fn connection_health(&self) -> ConnectionHealth {
+        self.transport.health()
+    }
+
+    pub fn last_command_id(&self) -> Option<String> {
+        self.transport.last_command_id()
+    }
+
+    pub fn command_outcome(&self, message_id: &str) -> Option<CommandOutcome> {
+        self.transport.command_outcome(message_id)
+    }
+
+    pub async fn set_hvac_mode(&self, zone_id: u8, mode: HvacMode) -> Result<()> {
+        let plan = command::hvac_mode(&self.systems.borrow(), zone_id, mode)?;
+        self.transport.publish_all(plan).await
+    }
+
+    pub async fn set_heat_setpoint(&self, zone_id: u8, temp: Temperature) -> Result<()> {
+        let plan = command::heat_setpoint(&self.systems.borrow(), zone_id, temp)?;
+        self.transport.publish_all(plan).await
+    }
+
+    pub async fn set_cool_setpoint(&self, zone_id: u8, temp: Temperature) -> Result<()> {
+        let plan = command::cool_setpoint(&self.systems.borrow(), zone_id, temp)?;
+        self.transport.publish_all(plan).await
+    }
+
+    pub async fn set_setpoints(&self, zone_id: u8, heat: Temperature, cool: Temperature) -> Result<()> {
+        let plan = command::setpoints(&self.systems.borrow(), zone_id, heat, cool)?;
+        self.transport.publish_all(plan).await
+    }
+
+    pub async fn set_fan_mode(&self, zone_id: u8, mode: FanMode) -> Result<()> {
+        let plan = command::fan_mode(&self.systems.borrow(), zone_id, mode)?;
+        self.transport.publish_all(plan).await
+    }
+
+    pub async fn set_away(&self, away: bool) -> Result<()> {
+        self.transport.publish_all(command::away(away)).await
+    }
+
+    pub async fn set_schedule_hold(&self, zone_id: u8, hold: bool) -> Result<()> {
+        let plan = command::schedule_hold(&self.systems.borrow(), zone_id, hold)?;
+        self.transport.publish_all(plan).await
+    }
+
+    pub async fn set_equipment_parameter(&self, equipment_id: u16, pid: u16, value: &str) -> Result<()> {
+        let plan = command::equipment_parameter(&self.systems.borrow(), equipment_id, pid, value)?;
+        self.transport.publish_all(plan).await
+    }
+
+    pub async fn set_parameter_typed<T: ParamValue>(
+        &self,
+        equipment_id: u16,
+        key: ParamKey,
+        value: T,
+    ) -> Result<()> {
+        let plan = command::parameter_typed(&self.systems.borrow(), equipment_id, key, value)?;
+        self.transport.publish_all(plan).await
+    }
+
+    pub async fn set_diag_level(&self, level: u8) -> Result<()> {
+        self.transport.publish_all(command::diag_level(level)).await
+    }
+
+    /// Stop the driver started by `S30Client::spawn` and disconnect, cancelling
+    /// any long poll in flight. No-op on handles from `S30Client::handle`.
+    pub async fn shutdown(&self) {
+        let Some(driver) = &self.driver else {
+            return;
+        };
+        let (tx, rx) = oneshot::channel();
+        if driver.send(tx).await.is_ok() {
+            let _ = rx.await;
+        }
+    }
+}
+
+impl S30Client {
+    /// Handle for issuing commands and reading state while this client keeps
+    /// being polled elsewhere. State seen through the handle is as of the last
+    /// `poll()`.
+    pub fn handle(&self) -> S30Handle {
+        S30Handle {
+            transport: self.transport(),
+            systems: self.watch_systems(),
+            events: self.event_sender(),
+            driver: None,
+        }
+    }
+
+    /// Hand the client to a background task that owns the poll loop.
+    ///
+    /// The driver connects, polls continuously, and on errors reconnects with
+    /// the builder's `ReconnectPolicy`. A 404 from Retrieve means the thermostat
+    /// forgot this app_id, so it re-subscribes immediately. Callbacks registered
+    /// on the builder keep firing from the driver task. The driver stops on
+    /// `S30Handle::shutdown`, when every handle from `spawn` has been dropped,
+    /// or on an error that isn't retryable, leaving the state `Failed`.
+    ///
+    /// Must be called from within a Tokio runtime.
+    pub fn spawn(self) -> S30Handle {
+        let (tx, rx) = mpsc::channel(1);
+        let handle = S30Handle {
+            driver: Some(tx),
+            ..self.handle()
+        };
+        tokio::spawn(drive(self, rx));
+        handle
+    }
+}
+
+async fn drive(mut client: S30Client, mut shutdown: mpsc::Receiver<oneshot::Sender<()>>) {
+    let mut backoff = Backoff::new(client.reconnect_policy().clone());
+    let mut consecutive_502 = 0;
+
+    loop {
+        tokio::select! {
+            request = shutdown.recv() => {
+                if client.is_connected()
+                    && let Err(e) = client.disconnect().await
+                {
+                    debug!(error = %e, "disconnect during shutdown failed");
+                }
+                if let Some(done) = request {
+                    let _ = done.send(());
+                }
+                return;
+            }
+            flow = step(&mut client, &mut backoff, &mut consecutive_502) => {
+                if flow.is_break() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// One connect attempt or poll, including any backoff it calls for. Breaks
+/// once the client has failed for good.
+async fn step(client: &mut S30Client, backoff: &mut Backoff, consecutive_502: &mut u32) -> ControlFlow<()> {
+    if !client.is_connected() {
+        match client.connect().await {
+            Ok(()) => {}
+            Err(e) if !e.is_retryable() => return fail(client, &e),
+            Err(e) => {
+                let delay = backoff.next_delay();
+                warn!(error = %e, ?delay, "connect failed, retrying");
+                tokio::time::sleep(delay).await;
+            }
+        }
+        return ControlFlow::Continue(());
+    }
+
+    match client.poll().await {
+        Ok(()) if client.last_poll_status() == Some(502) => {
+            *consecutive_502 += 1;
+            if *consecutive_502 >= MAX_CONSECUTIVE_502 {
+                debug!("repeated 502s, re-subscribing");
+                *consecutive_502 = 0;
+                client.mark_disconnected("repeated 502s");
+            }
+            tokio::time::sleep(backoff.next_delay()).await;
+        }
+        Ok(()) => {
+            *consecutive_502 = 0;
+            backoff.reset();
+        }
+        Err(e) if e.status() == Some(404) => {
+            debug!("endpoint not found, re-subscribing");
+            client.mark_disconnected("endpoint not found");
+        }
+        Err(e) if !e.is_retryable() => return fail(client, &e),
+        Err(e) => {
+            let delay = backoff.next_delay();
+            warn!(error = %e, ?delay, "poll failed, reconnecting");
+            client.mark_disconnected(&e.to_string());
+            tokio::time::sleep(delay).await;
+        }
+    }
+    ControlFlow::Continue(())
+}
+
+fn fail(client: &mut S30Client, e: &Error) -> ControlFlow<()> {
+    error!(error = %e, "connection failed and won't recover, stopping");
+    client.mark_failed(&e.to_string());
+    ControlFlow::Break(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_grows_and_caps() {
+        let mut backoff = Backoff::new(ReconnectPolicy {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(8),
+            multiplier: 2.0,
+        });
+        let delays: Vec<_> = (0..6).map(|_| backoff.next_delay()).collect();
+        for (i, d) in delays.iter().enumerate() {
+            let step = Duration::from_secs(1 << i.min(3));
+            assert!(*d >= step / 2 && *d <= step, "attempt {i}: {d:?}");
+        }
+        backoff.reset();
+        assert!(backoff.next_delay() <= Duration::from_secs(1));
+    }
+
+    #[test]
+    fn backoff_caps_without_overflow() {
+        let mut backoff = Backoff::new(ReconnectPolicy {
+            initial_delay: Duration::from_secs(u64::MAX / 2),
+            max_delay: Duration::MAX,
+            multiplier: 1e300,
+        });
+        for _ in 0..40 {
+            assert!(backoff.next_delay() <= Duration::MAX);
+        }
+    }
+
+    #[test]
+    fn jitter_in_unit_range() {
+        for _ in 0..100 {
+            let j = jitter();
+            assert!((0.0..1.0).contains(&j));
+        }
+    }
+}
@@ -2,7 +2,12 @@ use std::fmt;
 
 #[derive(Debug)]
 pub enum Error {
+    /// The request never got an HTTP response: connection refused, reset, TLS failure.
     Http(reqwest::Error),
+    /// The thermostat answered with a non-success status.
+    Status { endpoint: String, status: u16, body: String },
+    /// A poll body that isn't the JSON the thermostat normally sends.
+    MalformedResponse { body: String, reason: String },
     NotConnected,
     InvalidZone(u8),
     InvalidMode(String),
@@ -11,12 +16,33 @@ pub enum Error {
     Timeout,
     Io(std::io::Error),
     InvalidParameter { equipment_id: u16, pid: u16, reason: String },
+    InvalidBackup(String),
+    ParameterRejected { equipment_id: u16, pid: u16, requested: String, actual: String },
+    /// A parameter write was sent but not reported back in time. It may still
+    /// take effect, so check the reported value before sending it again.
+    ParameterUnconfirmed { equipment_id: u16, pid: u16, requested: String },
+    /// The thermostat presented a different certificate than the one pinned for it.
+    CertificateMismatch { host: String, expected: String, actual: String },
+    InvalidHost { host: String, reason: String },
+    InvalidProtocol(String),
+    InvalidAppId { app_id: String, reason: String },
+    InvalidReconnectPolicy(String),
+    InvalidLogPath { path: String, reason: String },
+    /// A recorded message log that can't be read back.
+    InvalidLog { line: usize, reason: String },
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Error::Http(e) => write!(f, "HTTP error: {e}"),
+            Error::Status { endpoint, status, body } if body.is_empty() => {
+                write!(f, "{endpoint} returned HTTP {status}")
+            }
+            Error::Status { endpoint, status, body } => {
+                write!(f, "{endpoint} returned HTTP {status}: {body}")
+            }
+            Error::MalformedResponse { reason, .. } => write!(f, "malformed response: {reason}"),
             Error::NotConnected => write!(f, "not connected"),
             Error::InvalidZone(id) => write!(f, "invalid zone: {id}"),
             Error::InvalidMode(mode) => write!(f, "invalid mode: {mode}"),
@@ -25,12 +51,55 @@ impl fmt::Display for Error {
                 f,
                 "invalid setpoints: heat {heat_c}°C, cool {cool_c}°C (need {deadband_c}°C deadband)"
             ),
-            Error::Timeout => write!(f, "poll timeout (no data)"),
+            Error::Timeout => write!(f, "timed out waiting for thermostat"),
             Error::Io(e) => write!(f, "IO error: {e}"),
             Error::InvalidParameter { equipment_id, pid, reason } => write!(
                 f,
                 "invalid parameter: equipment {equipment_id} pid {pid}: {reason}"
             ),
+            Error::InvalidBackup(msg) => write!(f, "invalid parameter backup: {msg}"),
+            Error::ParameterRejected { equipment_id, pid, requested, actual } => write!(
+                f,
+                "parameter rejected: equipment {equipment_id} pid {pid}: requested {requested}, thermostat reports {actual}"
+            ),
+            Error::ParameterUnconfirmed { equipment_id, pid, requested } => write!(
+                f,
+                "parameter unconfirmed: equipment {equipment_id} pid {pid}: {requested} sent but not reported back"
+            ),
+            Error::CertificateMismatch { host, expected, actual } => write!(
+                f,
+                "certificate for {host} changed: pinned {expected}, presented {actual}"
+            ),
+            Error::InvalidHost { host, reason } => write!(f, "invalid host {host:?}: {reason}"),
+            Error::InvalidProtocol(p) => write!(f, "invalid protocol {p:?} (expected http or https)"),
+            Error::InvalidAppId { app_id, reason } => write!(f, "invalid app_id {app_id:?}: {reason}"),
+            Error::InvalidReconnectPolicy(reason) => write!(f, "invalid reconnect policy: {reason}"),
+            Error::InvalidLogPath { path, reason } => write!(f, "cannot open message log {path}: {reason}"),
+            Error::InvalidLog { line, reason } => write!(f, "invalid message log at line {line}: {reason}"),
+        }
+    }
+}
+
+impl Error {
+    /// Whether reconnecting and trying again can succeed. False for errors
+    /// that need the caller to change something: bad configuration or input,
+    /// a rejected request, a changed certificate.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::Http(e) => !e.is_builder() && !e.is_redirect(),
+            // 404 means the thermostat forgot this app_id; re-subscribing fixes it.
+            Error::Status { status, .. } => matches!(status, 404 | 408 | 429 | 500..=599),
+            Error::MalformedResponse { .. } | Error::NotConnected | Error::Timeout => true,
+            _ => false,
+        }
+    }
+
+    /// HTTP status, if the thermostat answered with one.
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            Error::Status { status, .. } => Some(*status),
+            Error::Http(e) => e.status().map(|s| s.as_u16()),
+            _ => None,
         }
     }
 }
@@ -47,7 +116,11 @@ impl std::error::Error for Error {
 
 impl From<reqwest::Error> for Error {
     fn from(e: reqwest::Error) -> Self {
-        Error::Http(e)
+        if e.is_timeout() {
+            Error::Timeout
+        } else {
+            Error::Http(e)
+        }
     }
 }
 
@@ -0,0 +1,164 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use serde_json::{Map, Value};
+
+use crate::protocol::TARGET_LCC;
+use crate::redact::{RedactionPolicy, Redactor};
+use crate::replay::RecordedPoll;
+
+/// One captured message, trimmed to a single `Data` subtree.
+#[derive(Debug, Clone)]
+pub struct Fixture {
+    /// File stem: the subtree (`system`, `zones`) or the message type, with a
+    /// counter for later shapes (`zones_2`).
+    pub name: String,
+    /// The message as the thermostat sent it, redacted.
+    pub message: Value,
+}
+
+/// Split the messages of a recorded log into deduplicated, redacted fixtures.
+///
+/// Each `Data` key of a thermostat `PropertyChange` (`system`, `zones`,
+/// `schedules`, ...) becomes its own fixture; other message types and
+/// senders get one per `MessageType`. A subtree is only kept the first time
+/// it shows up with a new shape (set of key paths), so polls that just
+/// change values collapse into one fixture, while partial updates and fields
+/// added by new firmware each get their own. Keyframes are skipped, and
+/// fixtures keep log order so replaying them in order matches the capture.
+/// Give `policy` a salt to hash ids the same way on every run.
+pub fn extract_fixtures(polls: &[RecordedPoll], policy: RedactionPolicy) -> Vec<Fixture> {
+    let redactor = Redactor::new(policy);
+    let mut seen: HashSet<(String, BTreeSet<String>)> = HashSet::new();
+    let mut variants: HashMap<String, usize> = HashMap::new();
+    let mut fixtures = Vec::new();
+
+    let messages = polls
+        .iter()
+        .filter(|p| !p.keyframe)
+        .filter_map(|p| p.body.as_ref()?.get("messages")?.as_array())
+        .flatten();
+    for message in messages {
+        let mut message = message.clone();
+        redactor.message(&mut message);
+
+        for (kind, fixture) in split(message) {
+            let mut shape = BTreeSet::new();
+            key_paths(&fixture, "", &mut shape);
+            if !seen.insert((kind.clone(), shape)) {
+                continue;
+            }
+            let n = variants.entry(kind.clone()).or_default();
+            *n += 1;
+            let name = if *n == 1 { kind } else { format!("{kind}_{n}") };
+            fixtures.push(Fixture { name, message: fixture });
+        }
+    }
+    fixtures
+}
+
+/// A message as `(kind, message)` pairs: one per `Data` key for thermostat
+/// property changes, otherwise the whole message under its type.
+fn split(message: Value) -> Vec<(String, Value)> {
+    let field = |a: &str, b: &str| message.get(a).or_else(|| message.get(b)).and_then(|v| v.as_str());
+    let from_lcc = field("SenderID", "SenderId") == Some(TARGET_LCC);
+    let message_type = message.get("MessageType").and_then(|v| v.as_str()).unwrap_or("unknown").to_string();
+
+    match message.get("Data") {
+        Some(Value::Object(data)) if from_lcc && message_type == "PropertyChange" => data
+            .iter()
+            .map(|(key, value)| {
+                let mut fixture = message.clone();
+                fixture["Data"] = Value::Object(Map::from_iter([(key.clone(), value.clone())]));
+                (file_stem(key), fixture)
+            })
+            .collect(),
+        _ => vec![(file_stem(&message_type), message)],
+    }
+}
+
+/// Every key path under `value`, with array positions as `*`.
+fn key_paths(value: &Value, prefix: &str, out: &mut BTreeSet<String>) {
+    match value {
+        Value::Object(map) => {
+            for (key, child) in map {
+                let path = format!("{prefix}/{key}");
+                key_paths(child, &path, out);
+                out.insert(path);
+            }
+        }
+        Value::Array(items) => {
+            for child in items {
+                key_paths(child, &format!("{prefix}/*"), out);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// `PropertyChange` -> `property_change`, keeping only `[a-z0-9_]`.
+fn file_stem(s: &str) -> String {
+    let mut out = String::new();
+    for (i, c) in s.chars().enumerate() {
+        if c.is_ascii_uppercase() {
+            if i > 0 && !out.ends_with('_') {
+                out.push('_');
+            }
+            out.push(c.to_ascii_lowercase());
+        } else if c.is_ascii_alphanumeric() {
+            out.push(c);
+        } else if !out.ends_with('_') {
+            out.push('_');
+        }
+    }
+    if out.is_empty() { "unknown".to_string() } else { out }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn poll(messages: Value) -> RecordedPoll {
+        RecordedPoll {
+            ts: "2024-01-01T00:00:00Z".parse().unwrap(),
+            status: 200,
+            body: Some(json!({ "messages": messages })),
+            keyframe: false,
+        }
+    }
+
+    fn lcc(data: Value) -> Value {
+        json!({"MessageType": "PropertyChange", "SenderID": "LCC", "TargetID": "mapp1_me@example.com", "Data": data})
+    }
+
+    #[test]
+    fn splits_subtrees_and_dedups_by_shape() {
+        let polls = [
+            poll(json!([lcc(json!({"system": {"status": {"outdoorTemperature": 40}}, "zones": [{"id": 0}]}))])),
+            poll(json!([lcc(json!({"system": {"status": {"outdoorTemperature": 41}}}))])),
+            poll(json!([lcc(json!({"system": {"status": {"outdoorTemperature": 42, "diagLevel": 2}}}))])),
+            poll(json!([{"MessageType": "SetHomeAway", "SenderID": "mapp2", "Data": {}}])),
+        ];
+        let fixtures = extract_fixtures(&polls, RedactionPolicy::standard());
+        let names: Vec<&str> = fixtures.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["system", "zones", "system_2", "set_home_away"]);
+        assert_eq!(fixtures[1].message["Data"], json!({"zones": [{"id": 0}]}));
+        assert_eq!(fixtures[1].message["SenderID"], "LCC");
+        assert!(!fixtures[1].message.to_string().contains("me@example.com"));
+    }
+
+    #[test]
+    fn skips_keyframes() {
+        let mut keyframe = poll(json!([lcc(json!({"system": {}}))]));
+        keyframe.keyframe = true;
+        assert!(extract_fixtures(&[keyframe], RedactionPolicy::new()).is_empty());
+    }
+
+    #[test]
+    fn file_stems() {
+        assert_eq!(file_stem("PropertyChange"), "property_change");
+        assert_eq!(file_stem("rgw"), "rgw");
+        assert_eq!(file_stem("a.b-c"), "a_b_c");
+        assert_eq!(file_stem(""), "unknown");
+    }
+}
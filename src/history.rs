@@ -0,0 +1,168 @@
+use std::io::BufRead;
+use std::ops::RangeBounds;
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use serde_json::{Map, Value};
+
+use crate::client::S30Client;
+use crate::diff::merge_by_id;
+use crate::protocol::{parse_retrieve_value, RetrieveBody};
+use crate::replay::{open_log, read_polls, read_rotated, RecordedPoll};
+use crate::types::System;
+use crate::Result;
+
+/// One change to a state path, as seen in a message log.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathChange {
+    pub ts: DateTime<Utc>,
+    /// `None` when the path didn't exist yet.
+    pub old: Option<Value>,
+    pub new: Option<Value>,
+}
+
+/// Thermostat state over time, rebuilt from a message log.
+///
+/// State is the accumulation of every `Data` payload the thermostat sent, the
+/// same view a client builds while polling. Paths are JSON pointers into it,
+/// e.g. `/zones/0/status/period/hsp`; array elements are addressed by
+/// position, which for zones and equipment follows the order the thermostat
+/// first reported them in.
+pub struct LogHistory {
+    polls: Vec<RecordedPoll>,
+}
+
+impl LogHistory {
+    /// Open a log, decompressing it if it's gzip or zstd.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::from_reader(open_log(path.as_ref())?)
+    }
+
+    /// Open a rotated log: every rotated file of `path`, oldest first, then
+    /// `path` itself.
+    pub fn open_rotated(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self {
+            polls: read_rotated(path.as_ref())?,
+        })
+    }
+
+    pub fn from_reader(reader: impl BufRead) -> Result<Self> {
+        Ok(Self {
+            polls: read_polls(reader)?,
+        })
+    }
+
+    pub fn polls(&self) -> &[RecordedPoll] {
+        &self.polls
+    }
+
+    /// First and last poll timestamps.
+    pub fn span(&self) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+        Some((self.polls.first()?.ts, self.polls.last()?.ts))
+    }
+
+    /// Full JSON state as of `at`, including everything received at `at`.
+    pub fn state_at(&self, at: DateTime<Utc>) -> Value {
+        let mut state = Value::Object(Map::new());
+        for poll in self.polls.iter().take_while(|p| p.ts <= at) {
+            for data in thermostat_data(poll) {
+                merge_by_id(&mut state, &data);
+            }
+        }
+        state
+    }
+
+    /// Typed systems as of `at`, built by the client's own parser.
+    pub fn systems_at(&self, at: DateTime<Utc>) -> Vec<System> {
+        let mut client = S30Client::builder("127.0.0.1").build();
+        for poll in self.polls.iter().take_while(|p| p.ts <= at) {
+            if let Some(body) = &poll.body {
+                let _ = client.handle_value(body);
+            }
+        }
+        client.systems().to_vec()
+    }
+
+    /// Every change to the value at `pointer` whose timestamp falls in `range`.
+    pub fn path_history(&self, pointer: &str, range: impl RangeBounds<DateTime<Utc>>) -> Vec<PathChange> {
+        let mut state = Value::Object(Map::new());
+        let mut changes = Vec::new();
+        for poll in &self.polls {
+            let data = thermostat_data(poll);
+            if data.is_empty() {
+                continue;
+            }
+            let old = state.pointer(pointer).cloned();
+            for d in &data {
+                merge_by_id(&mut state, d);
+            }
+            let new = state.pointer(pointer).cloned();
+            if old != new && range.contains(&poll.ts) {
+                changes.push(PathChange { ts: poll.ts, old, new });
+            }
+        }
+        changes
+    }
+}
+
+/// `Data` of each thermostat message in a recorded poll.
+fn thermostat_data(poll: &RecordedPoll) -> Vec<Value> {
+    let Some(body) = &poll.body else {
+        return Vec::new();
+    };
+    match parse_retrieve_value(body) {
+        RetrieveBody::Messages { thermostat, .. } => {
+            thermostat.into_iter().filter_map(|m| m.data().cloned()).collect()
+        }
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOG: &str = r#"{"ts":"2024-01-01T01:00:00Z","dir":"poll","status":200,"full":true,"body":{"messages":[{"SenderID":"LCC","Data":{"zones":[{"id":0,"status":{"period":{"hsp":68,"hspC":20}}}]}}]}}
+{"ts":"2024-01-01T02:00:00Z","dir":"poll","status":200,"changes":[{"path":"messages","old":null,"new":[{"SenderID":"LCC","Data":{"system":{"status":{"outdoorTemperature":10}}}}]}]}
+{"ts":"2024-01-01T03:00:00Z","dir":"poll","status":204}
+{"ts":"2024-01-01T04:00:00Z","dir":"poll","status":200,"changes":[{"path":"messages","old":null,"new":[{"SenderID":"LCC","Data":{"zones":[{"id":0,"status":{"period":{"hsp":62,"hspC":16.5}}}]}}]}]}
+"#;
+
+    fn at(t: &str) -> DateTime<Utc> {
+        t.parse().unwrap()
+    }
+
+    #[test]
+    fn state_accumulates_until_timestamp() {
+        let history = LogHistory::from_reader(LOG.as_bytes()).unwrap();
+        let state = history.state_at(at("2024-01-01T03:30:00Z"));
+        assert_eq!(state.pointer("/zones/0/status/period/hsp").unwrap(), 68);
+        assert_eq!(state.pointer("/system/status/outdoorTemperature").unwrap(), 10);
+
+        let state = history.state_at(at("2024-01-01T04:00:00Z"));
+        assert_eq!(state.pointer("/zones/0/status/period/hsp").unwrap(), 62);
+
+        assert!(history.state_at(at("2024-01-01T00:00:00Z")).as_object().unwrap().is_empty());
+    }
+
+    #[test]
+    fn path_history_in_range() {
+        let history = LogHistory::from_reader(LOG.as_bytes()).unwrap();
+        let all = history.path_history("/zones/0/status/period/hsp", ..);
+        assert_eq!(all.len(), 2);
+        assert_eq!(all[0].old, None);
+        assert_eq!(all[1].old, Some(68.into()));
+        assert_eq!(all[1].new, Some(62.into()));
+
+        let later = history.path_history("/zones/0/status/period/hsp", at("2024-01-01T02:00:00Z")..);
+        assert_eq!(later, all[1..]);
+    }
+
+    #[test]
+    fn typed_systems_at_timestamp() {
+        let history = LogHistory::from_reader(LOG.as_bytes()).unwrap();
+        let systems = history.systems_at(at("2024-01-01T03:00:00Z"));
+        let hsp = systems[0].zones[0].heat_setpoint.unwrap();
+        assert_eq!(hsp.celsius(), 20.0);
+    }
+}
@@ -1,11 +1,41 @@
+mod backup;
 mod client;
+mod command;
+mod confirm;
 mod diff;
+mod driver;
 mod error;
+mod fixtures;
+mod history;
 mod logger;
+mod params;
+mod pinning;
 mod protocol;
+mod redact;
+mod replay;
+#[cfg(feature = "simulator")]
+mod simulator;
+mod sink;
+mod transport;
 mod types;
 
-pub use client::{S30Client, S30ClientBuilder};
+pub use backup::{
+    EquipmentBackup, ParameterBackup, RestoreAction, RestoreEntry, RestoreReport, BACKUP_VERSION,
+};
+pub use client::{Protocol, S30Client, S30ClientBuilder};
+pub use confirm::Confirmation;
+pub use driver::{ReconnectPolicy, S30Handle};
 pub use error::{Error, Result};
+pub use fixtures::{extract_fixtures, Fixture};
+pub use history::{LogHistory, PathChange};
 pub use logger::MessageLogMode;
+pub use pinning::{
+    certificate_fingerprint, FileFingerprintStore, FingerprintStore, MemoryFingerprintStore,
+};
+pub use redact::{RedactAction, RedactionPolicy};
+pub use replay::{RecordedPoll, Replayer};
+#[cfg(feature = "simulator")]
+pub use simulator::{Fault, Simulator, SimulatorBuilder};
+pub use sink::{LogCompression, LogRotation, MessageSink, RingBufferSink, WriterSink};
+pub use params::{EnumValue, ParamKey, ParamKind, ParamSpec, ParamValue, Percentage, EQUIP_TYPE_HEAT_PUMP};
 pub use types::*;
@@ -1,136 +1,329 @@
-use std::fs::{File, OpenOptions};
-use std::io::Write;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, Instant};
 
-use chrono::Utc;
-use serde_json::{json, Value};
+use chrono::{DateTime, Utc};
+use serde_json::{json, Map, Value};
 use tracing::warn;
 
-use crate::diff::diff_json;
+use crate::diff::{diff_json, merge_by_id};
+use crate::protocol::{parse_retrieve_value, RetrieveBody, TARGET_LCC};
+use crate::redact::{RedactionPolicy, Redactor};
+use crate::sink::{FileSink, LogCompression, LogRotation, MessageSink};
 
 pub enum MessageLogMode {
     Full,
     Diffed,
 }
 
+/// How a request or command went: HTTP status, latency and error.
+pub(crate) struct RequestOutcome {
+    pub sent_at: DateTime<Utc>,
+    pub latency: Duration,
+    /// `None` when no response arrived.
+    pub status: Option<u16>,
+    pub error: Option<String>,
+}
+
+impl RequestOutcome {
+    pub fn new(sent_at: DateTime<Utc>, started: Instant, result: &crate::Result<reqwest::Response>) -> Self {
+        let (status, error) = match result {
+            Ok(resp) => (Some(resp.status().as_u16()), None),
+            Err(e) => (e.status(), Some(e.to_string())),
+        };
+        Self {
+            sent_at,
+            latency: started.elapsed(),
+            status,
+            error,
+        }
+    }
+
+    fn write_to(&self, entry: &mut Value) {
+        entry["ts"] = json!(self.sent_at.to_rfc3339());
+        self.write_result_to(entry);
+    }
+
+    /// Status, latency and error, leaving the timestamp to the caller.
+    fn write_result_to(&self, entry: &mut Value) {
+        entry["status"] = json!(self.status);
+        entry["latency_ms"] = json!(self.latency.as_millis() as u64);
+        if let Some(error) = &self.error {
+            entry["error"] = json!(error);
+        }
+    }
+}
+
+/// Poll bodies between keyframes unless configured otherwise.
+pub(crate) const DEFAULT_KEYFRAME_EVERY: u32 = 1000;
+
+/// Everything about a message log besides its mode and destination.
+pub(crate) struct LogOptions {
+    /// File logs only.
+    pub rotation: Option<LogRotation>,
+    /// File logs only.
+    pub compression: LogCompression,
+    pub redaction: Option<RedactionPolicy>,
+    /// Poll bodies between periodic keyframes, whatever the sink.
+    pub keyframe_every: Option<u32>,
+}
+
+impl Default for LogOptions {
+    fn default() -> Self {
+        Self {
+            rotation: None,
+            compression: LogCompression::default(),
+            redaction: None,
+            keyframe_every: Some(DEFAULT_KEYFRAME_EVERY),
+        }
+    }
+}
+
 pub(crate) struct MessageLogger {
     mode: MessageLogMode,
-    file: File,
+    sink: Box<dyn MessageSink>,
+    redactor: Option<Redactor>,
+    /// Poll bodies between periodic keyframes.
+    keyframe_every: Option<u32>,
     previous_state: Option<Value>,
+    /// Every thermostat `Data` seen so far, written out as keyframes.
+    state: Value,
+    since_keyframe: u32,
+    /// Commands sent since the last poll with a body.
+    unanswered_commands: Vec<String>,
 }
 
 impl MessageLogger {
-    pub fn new(mode: MessageLogMode, path: &str) -> std::io::Result<Self> {
-        let file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(path)?;
-        Ok(Self {
+    /// A logger writing to `sink`. Rotation and compression options are for
+    /// files and ignored here.
+    pub fn new(mode: MessageLogMode, sink: Box<dyn MessageSink>, options: LogOptions) -> Self {
+        Self {
             mode,
-            file,
+            sink,
+            redactor: options.redaction.map(Redactor::new),
+            keyframe_every: options.keyframe_every,
             previous_state: None,
-        })
+            state: Value::Object(Map::new()),
+            since_keyframe: 0,
+            unanswered_commands: Vec::new(),
+        }
     }
 
-    pub fn log_request(&mut self, method: &str, path: &str, body: Option<&Value>) {
-        let entry = json!({
-            "ts": Utc::now().to_rfc3339(),
+    /// A logger writing to the file at `path`.
+    pub fn open(mode: MessageLogMode, path: &str, options: LogOptions) -> io::Result<Self> {
+        let sink = FileSink::open(Path::new(path), options.rotation.clone(), options.compression)?;
+        Ok(Self::new(mode, Box::new(sink), options))
+    }
+
+    pub fn log_request(&mut self, method: &str, path: &str, body: Option<&Value>, outcome: &RequestOutcome) {
+        self.rotate_if_due();
+        let redacted = body.and_then(|b| self.redacted(b, Redactor::message));
+        let body = redacted.as_ref().or(body);
+        let mut entry = json!({
             "dir": "req",
             "method": method,
             "path": path,
             "body": body,
         });
+        outcome.write_to(&mut entry);
         self.write_line(&entry);
     }
 
-    pub fn log_command(&mut self, action: &str, zone: Option<u8>, body: &Value) {
-        let entry = json!({
-            "ts": Utc::now().to_rfc3339(),
+    /// A published command. `id` is its MessageID, which the thermostat's
+    /// reply references and the next poll entry lists under `cmds`.
+    pub fn log_command(&mut self, action: &str, zone: Option<u8>, id: &str, body: &Value, outcome: &RequestOutcome) {
+        self.rotate_if_due();
+        let redacted = self.redacted(body, Redactor::data);
+        let body = redacted.as_ref().unwrap_or(body);
+        let mut entry = json!({
             "dir": "cmd",
             "action": action,
             "zone": zone,
+            "id": id,
             "body": body,
         });
+        outcome.write_to(&mut entry);
         self.write_line(&entry);
+        if outcome.error.is_none() {
+            self.unanswered_commands.push(id.to_string());
+        }
     }
 
-    pub fn log_poll(&mut self, status: u16, body: &Value) {
-        if status == 204 {
-            let entry = json!({
-                "ts": Utc::now().to_rfc3339(),
-                "dir": "poll",
-                "status": 204,
-            });
+    /// A connection lifecycle step: `connect`, `reconnect`, `lost` or
+    /// `disconnect`, with the error or reason if there was one.
+    pub fn log_connection(&mut self, event: &str, attempt: Option<u32>, error: Option<&str>) {
+        self.rotate_if_due();
+        let mut entry = json!({
+            "ts": Utc::now().to_rfc3339(),
+            "dir": "conn",
+            "event": event,
+        });
+        if let Some(attempt) = attempt {
+            entry["attempt"] = json!(attempt);
+        }
+        if let Some(error) = error {
+            entry["error"] = json!(error);
+        }
+        self.write_line(&entry);
+    }
+
+    /// A Retrieve, stamped when the response arrived. `body` is `None` for
+    /// polls without data: 204s, 502s and failures, which the outcome's
+    /// status and error describe.
+    pub fn log_poll(&mut self, outcome: &RequestOutcome, body: Option<&Value>) {
+        self.rotate_if_due();
+        let mut entry = json!({
+            "ts": Utc::now().to_rfc3339(),
+            "dir": "poll",
+        });
+        outcome.write_result_to(&mut entry);
+        let Some(body) = body else {
             self.write_line(&entry);
             return;
-        }
+        };
+        let redacted = self.redacted(body, Redactor::body);
+        let body = redacted.as_ref().unwrap_or(body);
 
         match self.mode {
             MessageLogMode::Full => {
-                let entry = json!({
-                    "ts": Utc::now().to_rfc3339(),
-                    "dir": "poll",
-                    "status": status,
-                    "body": body,
-                });
-                self.write_line(&entry);
+                entry["body"] = body.clone();
             }
-            MessageLogMode::Diffed => {
-                if self.previous_state.is_none() {
-                    let entry = json!({
-                        "ts": Utc::now().to_rfc3339(),
-                        "dir": "poll",
-                        "status": status,
-                        "full": true,
-                        "body": body,
-                    });
-                    self.write_line(&entry);
-                    self.previous_state = Some(body.clone());
-                } else {
-                    let prev = self.previous_state.as_ref().unwrap();
+            MessageLogMode::Diffed => match &self.previous_state {
+                None => {
+                    entry["full"] = json!(true);
+                    entry["body"] = body.clone();
+                }
+                Some(prev) => {
                     let mut changes = Vec::new();
                     diff_json(prev, body, "", &mut changes);
-
-                    let change_entries: Vec<Value> = changes
+                    let changes: Vec<Value> = changes
                         .iter()
-                        .map(|(path, old, new)| {
-                            json!({ "path": path, "old": old, "new": new })
-                        })
+                        .map(|(path, old, new)| json!({ "path": path, "old": old, "new": new }))
                         .collect();
-
-                    let entry = json!({
-                        "ts": Utc::now().to_rfc3339(),
-                        "dir": "poll",
-                        "status": status,
-                        "changes": change_entries,
-                    });
-                    self.write_line(&entry);
-                    self.previous_state = Some(body.clone());
+                    entry["changes"] = json!(changes);
                 }
+            },
+        }
+        self.tag_commands(&mut entry);
+        self.write_line(&entry);
+        if let MessageLogMode::Diffed = self.mode {
+            self.previous_state = Some(body.clone());
+        }
+        self.record_state(body);
+    }
+
+    /// List the commands sent since the previous data poll on `entry`, tying
+    /// each to the state change that followed it.
+    fn tag_commands(&mut self, entry: &mut Value) {
+        if !self.unanswered_commands.is_empty() {
+            entry["cmds"] = json!(std::mem::take(&mut self.unanswered_commands));
+        }
+    }
+
+    /// `value` after `redact`, or `None` without a redaction policy.
+    fn redacted(&self, value: &Value, redact: impl Fn(&Redactor, &mut Value)) -> Option<Value> {
+        let redactor = self.redactor.as_ref()?;
+        let mut value = value.clone();
+        redact(redactor, &mut value);
+        Some(value)
+    }
+
+    /// Fold a poll body into the keyframe state and write a periodic keyframe
+    /// when one is due.
+    fn record_state(&mut self, body: &Value) {
+        let RetrieveBody::Messages { thermostat, .. } = parse_retrieve_value(body) else {
+            return;
+        };
+        for data in thermostat.iter().filter_map(|m| m.data()) {
+            merge_by_id(&mut self.state, data);
+        }
+        self.since_keyframe += 1;
+        if self.keyframe_every.is_some_and(|n| self.since_keyframe >= n.max(1)) {
+            self.write_keyframe();
+        }
+    }
+
+    /// Write the accumulated state as a full poll body. Readers treat it like
+    /// any other full entry, and later diffs are taken against it.
+    fn write_keyframe(&mut self) {
+        self.since_keyframe = 0;
+        if self.state.as_object().is_some_and(|s| s.is_empty()) {
+            return;
+        }
+        let body = json!({"messages": [{
+            "MessageType": "PropertyChange",
+            "SenderID": TARGET_LCC,
+            "Data": self.state,
+        }]});
+        let entry = json!({
+            "ts": Utc::now().to_rfc3339(),
+            "dir": "poll",
+            "status": 200,
+            "full": true,
+            "keyframe": true,
+            "body": body,
+        });
+        self.write_line(&entry);
+        if let MessageLogMode::Diffed = self.mode {
+            self.previous_state = Some(body);
+        }
+    }
+
+    fn rotate_if_due(&mut self) {
+        match self.sink.rotate_if_due() {
+            Ok(true) => {
+                self.previous_state = None;
+                self.write_keyframe();
             }
+            Ok(false) => {}
+            Err(e) => warn!("failed to rotate message log: {e}"),
         }
     }
 
     fn write_line(&mut self, entry: &Value) {
         if let Ok(line) = serde_json::to_string(entry)
-            && let Err(e) = writeln!(self.file, "{line}")
+            && let Err(e) = self.sink.write_line(&line)
         {
             warn!("failed to write log entry: {e}");
         }
     }
+
+    fn flush(&mut self) {
+        if let Err(e) = self.sink.flush() {
+            warn!("failed to flush message log: {e}");
+        }
+    }
+}
+
+impl Drop for MessageLogger {
+    fn drop(&mut self) {
+        self.flush();
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::sink::rotated_files;
+    use std::fs;
     use std::io::Read;
     use tempfile::NamedTempFile;
 
+    fn sent(status: Option<u16>, error: Option<&str>) -> RequestOutcome {
+        RequestOutcome {
+            sent_at: Utc::now(),
+            latency: Duration::from_millis(12),
+            status,
+            error: error.map(str::to_string),
+        }
+    }
+
     #[test]
     fn log_request_writes_ndjson() {
         let tmp = NamedTempFile::new().unwrap();
         let path = tmp.path().to_str().unwrap();
-        let mut logger = MessageLogger::new(MessageLogMode::Full, path).unwrap();
-        logger.log_request("POST", "/Endpoints/app/Connect", None);
+        let mut logger = MessageLogger::open(MessageLogMode::Full, path, LogOptions::default()).unwrap();
+        logger.log_request("POST", "/Endpoints/app/Connect", None, &sent(Some(200), None));
 
         let mut contents = String::new();
         std::fs::File::open(path)
@@ -141,19 +334,22 @@ mod tests {
         assert_eq!(line["dir"], "req");
         assert_eq!(line["method"], "POST");
         assert!(line["ts"].as_str().is_some());
+        assert_eq!(line["status"], 200);
+        assert_eq!(line["latency_ms"], 12);
+        assert!(line.get("error").is_none());
     }
 
     #[test]
     fn diffed_mode_logs_full_first_then_changes() {
         let tmp = NamedTempFile::new().unwrap();
         let path = tmp.path().to_str().unwrap();
-        let mut logger = MessageLogger::new(MessageLogMode::Diffed, path).unwrap();
+        let mut logger = MessageLogger::open(MessageLogMode::Diffed, path, LogOptions::default()).unwrap();
 
         let body1 = json!({"system": {"status": {"outdoorTemperature": 72}}});
-        logger.log_poll(200, &body1);
+        logger.log_poll(&sent(Some(200), None), Some(&body1));
 
         let body2 = json!({"system": {"status": {"outdoorTemperature": 74}}});
-        logger.log_poll(200, &body2);
+        logger.log_poll(&sent(Some(200), None), Some(&body2));
 
         let mut contents = String::new();
         std::fs::File::open(path)
@@ -175,8 +371,8 @@ mod tests {
     fn log_poll_204() {
         let tmp = NamedTempFile::new().unwrap();
         let path = tmp.path().to_str().unwrap();
-        let mut logger = MessageLogger::new(MessageLogMode::Full, path).unwrap();
-        logger.log_poll(204, &json!(null));
+        let mut logger = MessageLogger::open(MessageLogMode::Full, path, LogOptions::default()).unwrap();
+        logger.log_poll(&sent(Some(204), None), None);
 
         let mut contents = String::new();
         std::fs::File::open(path)
@@ -188,12 +384,29 @@ mod tests {
         assert_eq!(line["status"], 204);
     }
 
+    #[test]
+    fn failed_polls_log_status_latency_and_error() {
+        let tmp = NamedTempFile::new().unwrap();
+        let mut logger = MessageLogger::open(MessageLogMode::Diffed, tmp.path().to_str().unwrap(), LogOptions::default()).unwrap();
+        logger.log_poll(&sent(None, Some("connection refused")), None);
+        logger.log_poll(&sent(Some(502), None), None);
+        logger.log_poll(&sent(Some(200), None), Some(&lcc(json!({"zones": []}))));
+
+        let lines = lines(tmp.path());
+        assert_eq!(lines[0]["status"], Value::Null);
+        assert_eq!(lines[0]["error"], "connection refused");
+        assert_eq!(lines[1]["status"], 502);
+        assert!(lines[1].get("body").is_none());
+        assert_eq!(lines[2]["full"], true, "failures don't count as a diff base");
+        assert!(lines.iter().all(|l| l["dir"] == "poll" && l["latency_ms"] == 12));
+    }
+
     #[test]
     fn log_command_captures_zone() {
         let tmp = NamedTempFile::new().unwrap();
         let path = tmp.path().to_str().unwrap();
-        let mut logger = MessageLogger::new(MessageLogMode::Full, path).unwrap();
-        logger.log_command("set_mode", Some(0), &json!({"systemMode": "heat"}));
+        let mut logger = MessageLogger::open(MessageLogMode::Full, path, LogOptions::default()).unwrap();
+        logger.log_command("set_mode", Some(0), "m1", &json!({"systemMode": "heat"}), &sent(Some(200), None));
 
         let mut contents = String::new();
         std::fs::File::open(path)
@@ -204,17 +417,42 @@ mod tests {
         assert_eq!(line["dir"], "cmd");
         assert_eq!(line["action"], "set_mode");
         assert_eq!(line["zone"], 0);
+        assert_eq!(line["id"], "m1");
+    }
+
+    #[test]
+    fn failed_commands_are_logged_but_not_tagged() {
+        let tmp = NamedTempFile::new().unwrap();
+        let mut logger = MessageLogger::open(MessageLogMode::Diffed, tmp.path().to_str().unwrap(), LogOptions::default()).unwrap();
+        let data = json!({"systemMode": "heat"});
+        logger.log_command("set_mode", Some(0), "m1", &data, &sent(None, Some("request timed out")));
+        logger.log_command("set_mode", Some(0), "m2", &data, &sent(Some(200), None));
+        logger.log_poll(&sent(Some(204), None), None);
+        logger.log_poll(&sent(Some(200), None), Some(&lcc(json!({"zones": []}))));
+        logger.log_poll(&sent(Some(200), None), Some(&lcc(json!({"zones": []}))));
+        logger.log_connection("lost", None, Some("endpoint not found"));
+        logger.log_connection("reconnect", Some(1), None);
+
+        let lines = lines(tmp.path());
+        assert_eq!(lines[0]["error"], "request timed out");
+        assert_eq!(lines[0]["status"], Value::Null);
+        assert_eq!(lines[2].get("cmds"), None);
+        assert_eq!(lines[3]["cmds"], json!(["m2"]));
+        assert_eq!(lines[4].get("cmds"), None);
+        assert_eq!(lines[5]["event"], "lost");
+        assert_eq!(lines[5]["error"], "endpoint not found");
+        assert_eq!(lines[6]["attempt"], 1);
     }
 
     #[test]
     fn diffed_mode_no_changes_logs_empty_array() {
         let tmp = NamedTempFile::new().unwrap();
         let path = tmp.path().to_str().unwrap();
-        let mut logger = MessageLogger::new(MessageLogMode::Diffed, path).unwrap();
+        let mut logger = MessageLogger::open(MessageLogMode::Diffed, path, LogOptions::default()).unwrap();
 
         let body = json!({"system": {"status": {"outdoorTemperature": 72}}});
-        logger.log_poll(200, &body);
-        logger.log_poll(200, &body);
+        logger.log_poll(&sent(Some(200), None), Some(&body));
+        logger.log_poll(&sent(Some(200), None), Some(&body));
 
         let mut contents = String::new();
         std::fs::File::open(path)
@@ -229,4 +467,198 @@ mod tests {
         assert_eq!(lines.len(), 2);
         assert_eq!(lines[1]["changes"].as_array().unwrap().len(), 0);
     }
+
+    fn lcc(data: Value) -> Value {
+        json!({"messages": [{"SenderID": "LCC", "Data": data}]})
+    }
+
+    fn lines(path: &Path) -> Vec<Value> {
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn rotates_by_size_and_prunes_old_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("lennox.ndjson");
+        fs::write(dir.path().join("lennox.ndjson.bak"), "keep me").unwrap();
+        let rotation = LogRotation {
+            max_bytes: Some(1),
+            max_files: Some(2),
+            ..LogRotation::default()
+        };
+        let mut logger = MessageLogger::open(MessageLogMode::Full, path.to_str().unwrap(), LogOptions { rotation: Some(rotation), ..LogOptions::default() }).unwrap();
+        for _ in 0..5 {
+            logger.log_request("POST", "/Messages/RequestData", None, &sent(Some(200), None));
+        }
+
+        assert_eq!(rotated_files(&path).unwrap().len(), 2);
+        assert_eq!(lines(&path).len(), 1);
+        assert!(dir.path().join("lennox.ndjson.bak").exists());
+    }
+
+    #[test]
+    fn rotated_file_opens_with_keyframe() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("lennox.ndjson");
+        let rotation = LogRotation {
+            max_bytes: Some(1),
+            ..LogRotation::default()
+        };
+        let mut logger = MessageLogger::open(MessageLogMode::Diffed, path.to_str().unwrap(), LogOptions { rotation: Some(rotation), ..LogOptions::default() }).unwrap();
+        logger.log_poll(&sent(Some(200), None), Some(&lcc(json!({"zones": [{"id": 0, "status": {"temperature": 70}}]}))));
+        logger.log_poll(&sent(Some(200), None), Some(&lcc(json!({"system": {"status": {"outdoorTemperature": 40}}}))));
+
+        let current = lines(&path);
+        assert_eq!(current[0]["keyframe"], true);
+        assert!(current[1].get("changes").is_some());
+
+        let history = crate::history::LogHistory::open(&path).unwrap();
+        let state = history.state_at(Utc::now());
+        assert_eq!(state.pointer("/zones/0/status/temperature").unwrap(), 70);
+        assert_eq!(state.pointer("/system/status/outdoorTemperature").unwrap(), 40);
+
+        let all = crate::history::LogHistory::open_rotated(&path).unwrap();
+        assert_eq!(all.polls().len(), 3);
+    }
+
+    #[test]
+    fn writes_periodic_keyframes() {
+        let tmp = NamedTempFile::new().unwrap();
+        let options = LogOptions {
+            keyframe_every: Some(2),
+            ..LogOptions::default()
+        };
+        let mut logger = MessageLogger::open(MessageLogMode::Diffed, tmp.path().to_str().unwrap(), options).unwrap();
+        for t in [70, 71, 72] {
+            logger.log_poll(&sent(Some(200), None), Some(&lcc(json!({"zones": [{"id": 0, "status": {"temperature": t}}]}))));
+        }
+
+        let keyframes: Vec<usize> = lines(tmp.path())
+            .iter()
+            .enumerate()
+            .filter(|(_, l)| l["keyframe"] == true)
+            .map(|(i, _)| i)
+            .collect();
+        assert_eq!(keyframes, vec![2]);
+    }
+
+    #[test]
+    fn channel_sink_gets_periodic_keyframes() {
+        let (tx, mut rx) = tokio::sync::mpsc::channel(100);
+        let options = LogOptions {
+            keyframe_every: Some(2),
+            ..LogOptions::default()
+        };
+        let mut logger = MessageLogger::new(MessageLogMode::Diffed, Box::new(tx), options);
+        for t in [70, 71, 72, 73] {
+            logger.log_poll(&sent(Some(200), None), Some(&lcc(json!({"zones": [{"id": 0, "status": {"temperature": t}}]}))));
+        }
+        drop(logger);
+
+        let mut received = Vec::new();
+        while let Ok(line) = rx.try_recv() {
+            received.push(line);
+        }
+        let keyframes = received.iter().filter(|l| l.contains(r#""keyframe":true"#)).count();
+        assert_eq!(keyframes, 2);
+
+        // Lose the opening full entry, as a full channel would: everything
+        // from the first keyframe on still replays.
+        let log = received[1..].join("\n");
+        let history = crate::history::LogHistory::from_reader(log.as_bytes()).unwrap();
+        let last = history.polls().iter().rev().find(|p| !p.keyframe).unwrap();
+        assert_eq!(last.body.as_ref().unwrap().pointer("/messages/0/Data/zones/0/status/temperature").unwrap(), 73);
+    }
+
+    #[test]
+    fn redaction_applies_to_every_entry() {
+        let tmp = NamedTempFile::new().unwrap();
+        let options = LogOptions {
+            redaction: Some(RedactionPolicy::standard().mask("/Data/zones/*/config/name")),
+            ..LogOptions::default()
+        };
+        let mut logger = MessageLogger::open(MessageLogMode::Diffed, tmp.path().to_str().unwrap(), options).unwrap();
+        let poll = |name: &str| {
+            json!({"messages": [{
+                "SenderID": "LCC",
+                "TargetID": "mapp1_owner@example.com",
+                "Data": {"system": {"config": {"name": "Cabin"}}, "zones": [{"id": 0, "config": {"name": name}}]},
+            }]})
+        };
+        let ok = sent(Some(200), None);
+        logger.log_request("POST", "/Messages/RequestData", Some(&json!({"SenderID": "mapp1_owner@example.com"})), &ok);
+        let rename = json!({"zones": [{"id": 0, "config": {"name": "Nursery"}}]});
+        logger.log_command("set_zone_name", Some(0), "m1", &rename, &ok);
+        logger.log_poll(&sent(Some(200), None), Some(&poll("Nursery")));
+        logger.log_poll(&sent(Some(200), None), Some(&poll("Office")));
+
+        let text = fs::read_to_string(tmp.path()).unwrap();
+        for secret in ["owner@example.com", "Cabin", "Nursery", "Office"] {
+            assert!(!text.contains(secret), "{secret} leaked: {text}");
+        }
+        let polls = crate::replay::read_polls(text.as_bytes()).unwrap();
+        assert_eq!(polls[0].body.as_ref().unwrap()["messages"][0]["SenderID"], "LCC");
+    }
+
+    #[cfg(all(feature = "gzip", feature = "zstd"))]
+    #[test]
+    fn compressed_full_logs_read_back_at_20x() {
+        let fixture: Value =
+            serde_json::from_str(include_str!("../tests/fixtures/system_heatpump_furnace.json")).unwrap();
+        let body = lcc(fixture["Data"].clone());
+        for compression in [LogCompression::Gzip, LogCompression::Zstd] {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("lennox.ndjson");
+            let mut logger =
+                MessageLogger::open(MessageLogMode::Full, path.to_str().unwrap(), LogOptions { compression, ..LogOptions::default() }).unwrap();
+            for _ in 0..20 {
+                logger.log_poll(&sent(Some(200), None), Some(&body));
+            }
+            drop(logger);
+
+            let raw = body.to_string().len() as u64 * 20;
+            let on_disk = fs::metadata(&path).unwrap().len();
+            assert!(raw / on_disk >= 20, "{compression:?}: {raw} -> {on_disk}");
+            let history = crate::history::LogHistory::open(&path).unwrap();
+            assert_eq!(history.polls().len(), 20);
+            assert_eq!(history.polls()[19].body.as_ref(), Some(&body));
+        }
+    }
+
+    #[cfg(all(feature = "gzip", feature = "zstd"))]
+    #[test]
+    fn unfinished_compressed_log_reads_to_last_flush() {
+        for compression in [LogCompression::Gzip, LogCompression::Zstd] {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("lennox.ndjson");
+            let mut logger =
+                MessageLogger::open(MessageLogMode::Diffed, path.to_str().unwrap(), LogOptions { compression, ..LogOptions::default() }).unwrap();
+            for t in [70, 71, 72] {
+                logger.log_poll(&sent(Some(200), None), Some(&lcc(json!({"zones": [{"id": 0, "status": {"temperature": t}}]}))));
+            }
+            logger.flush();
+            logger.log_poll(&sent(Some(200), None), Some(&lcc(json!({"zones": [{"id": 0, "status": {"temperature": 73}}]}))));
+
+            // What a crash would leave behind: no stream trailer.
+            let crashed = dir.path().join("crashed.ndjson");
+            fs::copy(&path, &crashed).unwrap();
+            drop(logger);
+
+            let history = crate::history::LogHistory::open(&crashed).unwrap();
+            assert_eq!(history.polls().len(), 3, "{compression:?}");
+            let state = history.state_at(Utc::now());
+            assert_eq!(state.pointer("/zones/0/status/temperature").unwrap(), 72);
+
+            // A restart appends a new member/frame to the finished file.
+            let mut logger =
+                MessageLogger::open(MessageLogMode::Diffed, path.to_str().unwrap(), LogOptions { compression, ..LogOptions::default() }).unwrap();
+            logger.log_poll(&sent(Some(200), None), Some(&lcc(json!({"zones": [{"id": 0, "status": {"temperature": 74}}]}))));
+            drop(logger);
+            assert_eq!(crate::history::LogHistory::open(&path).unwrap().polls().len(), 5);
+        }
+    }
 }
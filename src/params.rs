@@ -0,0 +1,377 @@
+use crate::types::{Descriptor, Equipment, Parameter, Temperature};
+
+/// Equipment type reported by the outdoor heat pump (`equipType` 19).
+pub const EQUIP_TYPE_HEAT_PUMP: u16 = 19;
+
+/// Identifies an equipment parameter by equipment type and pid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ParamKey {
+    pub equip_type: u16,
+    pub pid: u16,
+}
+
+impl ParamKey {
+    pub const HIGH_BALANCE_POINT: ParamKey = ParamKey::new(EQUIP_TYPE_HEAT_PUMP, 128);
+    pub const LOW_BALANCE_POINT: ParamKey = ParamKey::new(EQUIP_TYPE_HEAT_PUMP, 129);
+    pub const BALANCE_POINT_CONTROL: ParamKey = ParamKey::new(EQUIP_TYPE_HEAT_PUMP, 163);
+    pub const AUX_HEAT_ACTIVATION_THRESHOLD: ParamKey = ParamKey::new(EQUIP_TYPE_HEAT_PUMP, 176);
+    pub const HP_LOCKOUT_TIME: ParamKey = ParamKey::new(EQUIP_TYPE_HEAT_PUMP, 304);
+
+    pub const fn new(equip_type: u16, pid: u16) -> Self {
+        Self { equip_type, pid }
+    }
+
+    /// Catalog entry for this key, if it is a known parameter.
+    pub fn spec(&self) -> Option<&'static ParamSpec> {
+        CATALOG.iter().find(|s| s.key == *self)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParamKind {
+    Temperature,
+    Percentage,
+    Minutes,
+    Enum,
+    Bool,
+    Number,
+}
+
+/// Static description of a known installer parameter.
+#[derive(Debug, Clone)]
+pub struct ParamSpec {
+    pub key: ParamKey,
+    pub name: &'static str,
+    pub kind: ParamKind,
+    /// Unit used when the thermostat's descriptor doesn't carry one.
+    pub unit: &'static str,
+    pub description: &'static str,
+}
+
+impl ParamSpec {
+    /// Every catalogued parameter.
+    pub fn all() -> &'static [ParamSpec] {
+        CATALOG
+    }
+
+    /// Find a catalogued parameter by its semantic name (case-insensitive).
+    pub fn by_name(name: &str) -> Option<&'static ParamSpec> {
+        CATALOG.iter().find(|s| s.name.eq_ignore_ascii_case(name))
+    }
+}
+
+/// Only parameters seen in captures, each noted with where it was seen. Others
+/// can still be read and written by `ParamKey::new(equip_type, pid)`.
+const CATALOG: &[ParamSpec] = &[
+    // Heat pump capture in tests/client_tests.rs (`High Balance Point`, range in F).
+    ParamSpec {
+        key: ParamKey::HIGH_BALANCE_POINT,
+        name: "high_balance_point",
+        kind: ParamKind::Temperature,
+        unit: "F",
+        description: "Outdoor temperature above which auxiliary heat is locked out",
+    },
+    // Heat pump capture in tests/client_tests.rs (`Low Balance Point`, range in F).
+    ParamSpec {
+        key: ParamKey::LOW_BALANCE_POINT,
+        name: "low_balance_point",
+        kind: ParamKind::Temperature,
+        unit: "F",
+        description: "Outdoor temperature below which the heat pump is locked out",
+    },
+    // `Equipment::balance_point_enabled`, which reads it as "0"/"1".
+    ParamSpec {
+        key: ParamKey::BALANCE_POINT_CONTROL,
+        name: "balance_point_control",
+        kind: ParamKind::Bool,
+        unit: "",
+        description: "Enables the high/low balance point lockouts",
+    },
+    // `Equipment::aux_heat_activation_threshold`.
+    ParamSpec {
+        key: ParamKey::AUX_HEAT_ACTIVATION_THRESHOLD,
+        name: "aux_heat_activation_threshold",
+        kind: ParamKind::Temperature,
+        unit: "F",
+        description: "Threshold at which auxiliary heat is brought on",
+    },
+    // Heat pump capture in tests/client_tests.rs (`HP Lockout Time`, 60-240 min).
+    ParamSpec {
+        key: ParamKey::HP_LOCKOUT_TIME,
+        name: "hp_lockout_time",
+        kind: ParamKind::Minutes,
+        unit: "min",
+        description: "How long the heat pump stays locked out",
+    },
+];
+
+/// A Rust type a parameter's string value can be read as and written from.
+pub trait ParamValue: Sized {
+    fn from_parameter(param: &Parameter, unit: &str) -> Option<Self>;
+    fn to_parameter(&self, unit: &str) -> String;
+}
+
+impl ParamValue for f64 {
+    fn from_parameter(param: &Parameter, _unit: &str) -> Option<Self> {
+        param.value.parse().ok()
+    }
+
+    fn to_parameter(&self, _unit: &str) -> String {
+        self.to_string()
+    }
+}
+
+impl ParamValue for u32 {
+    fn from_parameter(param: &Parameter, _unit: &str) -> Option<Self> {
+        param.value.parse().ok()
+    }
+
+    fn to_parameter(&self, _unit: &str) -> String {
+        self.to_string()
+    }
+}
+
+impl ParamValue for bool {
+    fn from_parameter(param: &Parameter, _unit: &str) -> Option<Self> {
+        match param.value.as_str() {
+            "1" => Some(true),
+            "0" => Some(false),
+            _ => None,
+        }
+    }
+
+    fn to_parameter(&self, _unit: &str) -> String {
+        if *self { "1" } else { "0" }.to_string()
+    }
+}
+
+/// Radio parameters read as their option label; everything else reads raw.
+impl ParamValue for String {
+    fn from_parameter(param: &Parameter, _unit: &str) -> Option<Self> {
+        match &param.descriptor {
+            Descriptor::Radio { options } => options.get(&param.value).cloned(),
+            _ => Some(param.value.clone()),
+        }
+    }
+
+    fn to_parameter(&self, _unit: &str) -> String {
+        self.clone()
+    }
+}
+
+impl ParamValue for Temperature {
+    fn from_parameter(param: &Parameter, unit: &str) -> Option<Self> {
+        let v: f64 = param.value.parse().ok()?;
+        match unit {
+            "F" => Some(Temperature::from_fahrenheit(v)),
+            "C" => Some(Temperature::from_celsius(v)),
+            _ => None,
+        }
+    }
+
+    fn to_parameter(&self, unit: &str) -> String {
+        match unit {
+            "C" => self.to_lennox_celsius().to_string(),
+            _ => self.to_lennox_fahrenheit().to_string(),
+        }
+    }
+}
+
+/// A percentage parameter, 0 to 100.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Percentage(pub f64);
+
+impl ParamValue for Percentage {
+    fn from_parameter(param: &Parameter, _unit: &str) -> Option<Self> {
+        let v: f64 = param.value.parse().ok()?;
+        (0.0..=100.0).contains(&v).then_some(Percentage(v))
+    }
+
+    fn to_parameter(&self, _unit: &str) -> String {
+        self.0.to_string()
+    }
+}
+
+/// A radio parameter's selected option.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnumValue {
+    /// Option id as the thermostat stores it, e.g. `"1"`.
+    pub value: String,
+    /// The option's label from the descriptor, e.g. `"Normal"`.
+    pub label: Option<String>,
+}
+
+impl EnumValue {
+    /// An option to write, by id or label; validation maps labels to ids.
+    pub fn new(option: impl Into<String>) -> Self {
+        Self {
+            value: option.into(),
+            label: None,
+        }
+    }
+}
+
+/// Only reads radio parameters, and only values that are one of the options.
+impl ParamValue for EnumValue {
+    fn from_parameter(param: &Parameter, _unit: &str) -> Option<Self> {
+        let Descriptor::Radio { options } = &param.descriptor else {
+            return None;
+        };
+        Some(EnumValue {
+            value: param.value.clone(),
+            label: Some(options.get(&param.value)?.clone()),
+        })
+    }
+
+    fn to_parameter(&self, _unit: &str) -> String {
+        self.value.clone()
+    }
+}
+
+/// Unit to interpret a parameter in: the descriptor's own unit, else the catalog's.
+pub(crate) fn effective_unit(param: &Parameter, key: ParamKey) -> &str {
+    match &param.descriptor {
+        Descriptor::Range { unit, .. } if !unit.is_empty() => unit,
+        _ => key.spec().map(|s| s.unit).unwrap_or(""),
+    }
+}
+
+impl Equipment {
+    /// Read a parameter as a typed value. Returns None if this equipment is a
+    /// different type than `key`, the pid is absent, or the value doesn't parse.
+    pub fn get<T: ParamValue>(&self, key: ParamKey) -> Option<T> {
+        if self.equip_type != key.equip_type {
+            return None;
+        }
+        let param = self.parameter(key.pid)?;
+        T::from_parameter(param, effective_unit(param, key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn range_param(pid: u16, value: &str, unit: &str) -> Parameter {
+        Parameter {
+            pid,
+            name: String::new(),
+            value: value.to_string(),
+            enabled: true,
+            descriptor: Descriptor::Range { min: -20.0, max: 240.0, inc: 1.0, unit: unit.to_string() },
+        }
+    }
+
+    fn heat_pump(params: Vec<Parameter>) -> Equipment {
+        Equipment {
+            id: 1,
+            equip_type: EQUIP_TYPE_HEAT_PUMP,
+            parameters: params.into_iter().map(|p| (p.pid, p)).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn catalog_lookup() {
+        let spec = ParamKey::HIGH_BALANCE_POINT.spec().unwrap();
+        assert_eq!(spec.name, "high_balance_point");
+        assert_eq!(spec.kind, ParamKind::Temperature);
+        assert_eq!(ParamSpec::by_name("HP_LOCKOUT_TIME").unwrap().key.pid, 304);
+        assert!(ParamKey::new(99, 1).spec().is_none());
+    }
+
+    #[test]
+    fn typed_temperature_uses_descriptor_unit() {
+        let equip = heat_pump(vec![range_param(128, "50", "F")]);
+        let t: Temperature = equip.get(ParamKey::HIGH_BALANCE_POINT).unwrap();
+        assert!((t.celsius() - 10.0).abs() < 0.01);
+        assert_eq!(equip.get::<f64>(ParamKey::HIGH_BALANCE_POINT), Some(50.0));
+    }
+
+    #[test]
+    fn typed_temperature_falls_back_to_catalog_unit() {
+        let mut param = range_param(176, "2", "");
+        param.descriptor = Descriptor::String { max_len: None };
+        let equip = heat_pump(vec![param]);
+        let t: Temperature = equip.get(ParamKey::AUX_HEAT_ACTIVATION_THRESHOLD).unwrap();
+        assert_eq!(t.to_lennox_fahrenheit(), 2);
+    }
+
+    #[test]
+    fn typed_bool_and_enum() {
+        let mut options = BTreeMap::new();
+        options.insert("0".to_string(), "Disabled".to_string());
+        options.insert("1".to_string(), "Enabled".to_string());
+        let param = Parameter {
+            pid: 163,
+            name: "Balance Point Control".to_string(),
+            value: "1".to_string(),
+            enabled: true,
+            descriptor: Descriptor::Radio { options },
+        };
+        let equip = heat_pump(vec![param]);
+        assert_eq!(equip.get::<bool>(ParamKey::BALANCE_POINT_CONTROL), Some(true));
+        assert_eq!(
+            equip.get::<String>(ParamKey::BALANCE_POINT_CONTROL).as_deref(),
+            Some("Enabled")
+        );
+    }
+
+    #[test]
+    fn typed_percentage() {
+        let equip = heat_pump(vec![range_param(22, "70", "%")]);
+        let key = ParamKey::new(EQUIP_TYPE_HEAT_PUMP, 22);
+        assert_eq!(equip.get(key), Some(Percentage(70.0)));
+        assert_eq!(Percentage(37.5).to_parameter("%"), "37.5");
+
+        let equip = heat_pump(vec![range_param(22, "140", "%")]);
+        assert_eq!(equip.get::<Percentage>(key), None);
+    }
+
+    #[test]
+    fn typed_enum() {
+        let mut options = BTreeMap::new();
+        options.insert("30".to_string(), "30 minutes".to_string());
+        options.insert("60".to_string(), "60 minutes".to_string());
+        let mut param = Parameter {
+            pid: 131,
+            name: "Defrost Interval".to_string(),
+            value: "60".to_string(),
+            enabled: true,
+            descriptor: Descriptor::Radio { options },
+        };
+        let equip = heat_pump(vec![param.clone()]);
+        let interval: EnumValue = equip.get(ParamKey::new(EQUIP_TYPE_HEAT_PUMP, 131)).unwrap();
+        assert_eq!(interval.value, "60");
+        assert_eq!(interval.label.as_deref(), Some("60 minutes"));
+        assert_eq!(EnumValue::new("30 minutes").to_parameter(""), "30 minutes");
+
+        param.value = "45".to_string();
+        assert_eq!(EnumValue::from_parameter(&param, ""), None);
+        assert_eq!(EnumValue::from_parameter(&range_param(131, "60", ""), ""), None);
+    }
+
+    #[test]
+    fn catalog_keys_and_names_are_unique() {
+        let all = ParamSpec::all();
+        for (i, spec) in all.iter().enumerate() {
+            assert!(all[i + 1..].iter().all(|s| s.key != spec.key && s.name != spec.name), "{}", spec.name);
+        }
+    }
+
+    #[test]
+    fn get_rejects_other_equipment_type() {
+        let mut equip = heat_pump(vec![range_param(128, "50", "F")]);
+        equip.equip_type = 7;
+        assert_eq!(equip.get::<f64>(ParamKey::HIGH_BALANCE_POINT), None);
+    }
+
+    #[test]
+    fn temperature_formats_in_parameter_unit() {
+        let t = Temperature::from_celsius(10.0);
+        assert_eq!(t.to_parameter("F"), "50");
+        assert_eq!(t.to_parameter("C"), "10");
+        assert_eq!(true.to_parameter(""), "1");
+    }
+}
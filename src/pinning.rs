@@ -0,0 +1,294 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{DigitallySignedStruct, SignatureScheme};
+use tracing::{debug, warn};
+
+/// Persists pinned certificate fingerprints, keyed by thermostat host.
+///
+/// Fingerprints are lowercase hex SHA-256 of the DER certificate.
+pub trait FingerprintStore: Send + Sync {
+    fn load(&self, host: &str) -> io::Result<Option<String>>;
+    fn save(&self, host: &str, fingerprint: &str) -> io::Result<()>;
+    fn remove(&self, host: &str) -> io::Result<()>;
+}
+
+impl<T: FingerprintStore + ?Sized> FingerprintStore for Arc<T> {
+    fn load(&self, host: &str) -> io::Result<Option<String>> {
+        (**self).load(host)
+    }
+
+    fn save(&self, host: &str, fingerprint: &str) -> io::Result<()> {
+        (**self).save(host, fingerprint)
+    }
+
+    fn remove(&self, host: &str) -> io::Result<()> {
+        (**self).remove(host)
+    }
+}
+
+/// Pins held for the life of the process.
+#[derive(Debug, Default)]
+pub struct MemoryFingerprintStore {
+    pins: Mutex<BTreeMap<String, String>>,
+}
+
+impl MemoryFingerprintStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl FingerprintStore for MemoryFingerprintStore {
+    fn load(&self, host: &str) -> io::Result<Option<String>> {
+        Ok(self.pins.lock().unwrap().get(host).cloned())
+    }
+
+    fn save(&self, host: &str, fingerprint: &str) -> io::Result<()> {
+        self.pins
+            .lock()
+            .unwrap()
+            .insert(host.to_string(), fingerprint.to_string());
+        Ok(())
+    }
+
+    fn remove(&self, host: &str) -> io::Result<()> {
+        self.pins.lock().unwrap().remove(host);
+        Ok(())
+    }
+}
+
+/// Pins stored as a JSON object of host to fingerprint. Several clients may
+/// share one file.
+#[derive(Debug)]
+pub struct FileFingerprintStore {
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl FileFingerprintStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            lock: Mutex::new(()),
+        }
+    }
+
+    fn read(&self) -> io::Result<BTreeMap<String, String>> {
+        match std::fs::read_to_string(&self.path) {
+            Ok(text) => serde_json::from_str(&text)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn write(&self, pins: &BTreeMap<String, String>) -> io::Result<()> {
+        let json = serde_json::to_string_pretty(pins).expect("pins serialize to JSON");
+        std::fs::write(&self.path, json)
+    }
+}
+
+impl FingerprintStore for FileFingerprintStore {
+    fn load(&self, host: &str) -> io::Result<Option<String>> {
+        let _guard = self.lock.lock().unwrap();
+        Ok(self.read()?.remove(host))
+    }
+
+    fn save(&self, host: &str, fingerprint: &str) -> io::Result<()> {
+        let _guard = self.lock.lock().unwrap();
+        let mut pins = self.read()?;
+        pins.insert(host.to_string(), fingerprint.to_string());
+        self.write(&pins)
+    }
+
+    fn remove(&self, host: &str) -> io::Result<()> {
+        let _guard = self.lock.lock().unwrap();
+        let mut pins = self.read()?;
+        if pins.remove(host).is_some() {
+            self.write(&pins)?;
+        }
+        Ok(())
+    }
+}
+
+/// Lowercase hex SHA-256 of a DER certificate.
+pub fn certificate_fingerprint(der: &[u8]) -> String {
+    let digest = ring::digest::digest(&ring::digest::SHA256, der);
+    digest.as_ref().iter().fold(String::new(), |mut out, b| {
+        let _ = write!(out, "{b:02x}");
+        out
+    })
+}
+
+/// Trust-on-first-use verifier: accepts and records the first certificate
+/// seen for the host, then only that one. Chain and hostname checks are
+/// skipped (the thermostat's certificate is self-signed), but handshake
+/// signatures are still verified so the peer must hold the pinned key.
+#[derive(Debug)]
+pub(crate) struct TofuVerifier {
+    host: String,
+    store: Arc<dyn FingerprintStore>,
+    provider: Arc<CryptoProvider>,
+    mismatch: Mutex<Option<(String, String)>>,
+}
+
+impl std::fmt::Debug for dyn FingerprintStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("FingerprintStore")
+    }
+}
+
+impl TofuVerifier {
+    pub fn new(host: String, store: Arc<dyn FingerprintStore>) -> Self {
+        Self {
+            host,
+            store,
+            provider: Arc::new(rustls::crypto::ring::default_provider()),
+            mismatch: Mutex::new(None),
+        }
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    pub fn store(&self) -> &dyn FingerprintStore {
+        self.store.as_ref()
+    }
+
+    /// `(expected, actual)` from the most recent rejected handshake.
+    pub fn take_mismatch(&self) -> Option<(String, String)> {
+        self.mismatch.lock().unwrap().take()
+    }
+
+    pub fn client_config(self: Arc<Self>) -> rustls::ClientConfig {
+        rustls::ClientConfig::builder_with_provider(self.provider.clone())
+            .with_safe_default_protocol_versions()
+            .expect("ring provider supports default protocol versions")
+            .dangerous()
+            .with_custom_certificate_verifier(self)
+            .with_no_client_auth()
+    }
+}
+
+impl ServerCertVerifier for TofuVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let actual = certificate_fingerprint(end_entity);
+        let pinned = self
+            .store
+            .load(&self.host)
+            .map_err(|e| rustls::Error::General(format!("loading pinned fingerprint: {e}")))?;
+
+        match pinned {
+            None => {
+                debug!(host = %self.host, fingerprint = %actual, "pinning certificate on first use");
+                self.store
+                    .save(&self.host, &actual)
+                    .map_err(|e| rustls::Error::General(format!("saving fingerprint: {e}")))?;
+                Ok(ServerCertVerified::assertion())
+            }
+            Some(expected) if expected.eq_ignore_ascii_case(&actual) => {
+                Ok(ServerCertVerified::assertion())
+            }
+            Some(expected) => {
+                warn!(host = %self.host, %expected, %actual, "certificate fingerprint changed");
+                *self.mismatch.lock().unwrap() = Some((expected, actual));
+                Err(rustls::Error::General(
+                    "certificate does not match pinned fingerprint".to_string(),
+                ))
+            }
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn verify(verifier: &TofuVerifier, der: &[u8]) -> Result<ServerCertVerified, rustls::Error> {
+        verifier.verify_server_cert(
+            &CertificateDer::from(der.to_vec()),
+            &[],
+            &ServerName::try_from("thermostat").unwrap(),
+            &[],
+            UnixTime::now(),
+        )
+    }
+
+    #[test]
+    fn fingerprint_is_sha256_hex() {
+        assert_eq!(
+            certificate_fingerprint(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
+
+    #[test]
+    fn pins_first_certificate_and_rejects_others() {
+        let store = Arc::new(MemoryFingerprintStore::new());
+        let verifier = TofuVerifier::new("10.0.0.2".to_string(), store.clone());
+
+        assert!(verify(&verifier, b"cert-a").is_ok());
+        assert_eq!(
+            store.load("10.0.0.2").unwrap(),
+            Some(certificate_fingerprint(b"cert-a"))
+        );
+        assert!(verify(&verifier, b"cert-a").is_ok());
+
+        assert!(verify(&verifier, b"cert-b").is_err());
+        let (expected, actual) = verifier.take_mismatch().unwrap();
+        assert_eq!(expected, certificate_fingerprint(b"cert-a"));
+        assert_eq!(actual, certificate_fingerprint(b"cert-b"));
+    }
+
+    #[test]
+    fn file_store_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FileFingerprintStore::new(dir.path().join("pins.json"));
+        assert_eq!(store.load("a").unwrap(), None);
+        store.save("a", "aa").unwrap();
+        store.save("b", "bb").unwrap();
+        assert_eq!(store.load("a").unwrap().as_deref(), Some("aa"));
+        store.remove("a").unwrap();
+        assert_eq!(store.load("a").unwrap(), None);
+        assert_eq!(store.load("b").unwrap().as_deref(), Some("bb"));
+    }
+}
@@ -23,11 +23,15 @@ pub fn subscribe_message(app_id: &str) -> Value {
     })
 }
 
-pub fn command_message(app_id: &str, data: Value) -> Value {
+pub fn new_message_id() -> String {
+    Uuid::new_v4().to_string()
+}
+
+pub fn command_message(app_id: &str, message_id: &str, data: Value) -> Value {
     json!({
         "MessageType": "Command",
         "SenderID": app_id,
-        "MessageID": Uuid::new_v4().to_string(),
+        "MessageID": message_id,
         "TargetID": TARGET_LCC,
         "Data": data
     })
@@ -143,31 +147,135 @@ pub fn set_parameter_data(equip_type: u16, pid: u16, value: &str) -> Value {
     })
 }
 
-pub fn parse_retrieve_response(body: &str) -> Vec<Value> {
-    let parsed: Value = match serde_json::from_str(body) {
-        Ok(v) => v,
-        Err(_) => return vec![],
+/// One message from a Retrieve response, whatever its sender or type.
+#[derive(Debug, Clone)]
+pub struct RetrievedMessage {
+    pub sender_id: Option<String>,
+    pub message_type: Option<String>,
+    pub message_id: Option<String>,
+    pub raw: Value,
+}
+
+impl RetrievedMessage {
+    fn from_value(raw: Value) -> Self {
+        let field = |a: &str, b: &str| {
+            raw.get(a)
+                .or_else(|| raw.get(b))
+                .and_then(|v| v.as_str())
+                .map(str::to_string)
+        };
+        Self {
+            sender_id: field("SenderID", "SenderId"),
+            message_type: raw.get("MessageType").and_then(|v| v.as_str()).map(str::to_string),
+            message_id: field("MessageID", "MessageId"),
+            raw,
+        }
+    }
+
+    pub fn is_from_lcc(&self) -> bool {
+        self.sender_id.as_deref() == Some(TARGET_LCC)
+    }
+
+    pub fn data(&self) -> Option<&Value> {
+        self.raw.get("Data")
+    }
+
+    /// True if any string in the message equals `id`. A message's own MessageID
+    /// only counts when someone other than `app_id` sent it, so our own echoed
+    /// commands don't match themselves.
+    pub fn references(&self, id: &str, app_id: &str) -> bool {
+        if self.message_id.as_deref() == Some(id) {
+            return self.sender_id.as_deref() != Some(app_id);
+        }
+        match &self.raw {
+            Value::Object(map) => map
+                .iter()
+                .filter(|(k, _)| *k != "MessageID" && *k != "MessageId")
+                .any(|(_, v)| contains_string(v, id)),
+            _ => false,
+        }
+    }
+}
+
+fn contains_string(value: &Value, needle: &str) -> bool {
+    match value {
+        Value::String(s) => s == needle,
+        Value::Array(items) => items.iter().any(|v| contains_string(v, needle)),
+        Value::Object(map) => map.values().any(|v| contains_string(v, needle)),
+        _ => false,
+    }
+}
+
+/// A Retrieve body, classified.
+#[derive(Debug)]
+pub enum RetrieveBody {
+    /// An empty body, or an object without `messages`.
+    Empty,
+    /// Messages split by sender: the thermostat itself, and everyone else
+    /// (command replies, echoes, other LAN clients).
+    Messages {
+        thermostat: Vec<RetrievedMessage>,
+        other: Vec<RetrievedMessage>,
+    },
+    /// Not the JSON the thermostat normally sends, e.g. a truncated body.
+    Malformed { body: String, reason: String },
+}
+
+pub fn parse_retrieve(body: &str) -> RetrieveBody {
+    if body.trim().is_empty() {
+        return RetrieveBody::Empty;
+    }
+    let malformed = |reason: String| RetrieveBody::Malformed {
+        body: body.to_string(),
+        reason,
     };
-    let messages = match parsed.get("messages") {
-        Some(Value::Array(msgs)) => msgs,
-        _ => return vec![],
+    match serde_json::from_str(body) {
+        // Report the body as received, not re-serialized.
+        Ok(parsed) => match parse_retrieve_value(&parsed) {
+            RetrieveBody::Malformed { reason, .. } => malformed(reason),
+            parsed => parsed,
+        },
+        Err(e) => malformed(e.to_string()),
+    }
+}
+
+/// `parse_retrieve` for a body that's already JSON, e.g. one read back from
+/// a message log.
+pub fn parse_retrieve_value(parsed: &Value) -> RetrieveBody {
+    let malformed = |reason: String| RetrieveBody::Malformed {
+        body: parsed.to_string(),
+        reason,
     };
-    messages
-        .iter()
-        .filter_map(|msg| {
-            let sender = msg.get("SenderID").or_else(|| msg.get("SenderId"));
-            match sender.and_then(|v| v.as_str()) {
-                Some(TARGET_LCC) => msg.get("Data").cloned(),
-                _ => None,
-            }
-        })
-        .collect()
+    if !parsed.is_object() {
+        return malformed("expected a JSON object".to_string());
+    }
+    match parsed.get("messages") {
+        Some(Value::Array(msgs)) => {
+            let (thermostat, other) = msgs
+                .iter()
+                .cloned()
+                .map(RetrievedMessage::from_value)
+                .partition(RetrievedMessage::is_from_lcc);
+            RetrieveBody::Messages { thermostat, other }
+        }
+        None | Some(Value::Null) => RetrieveBody::Empty,
+        Some(_) => malformed("`messages` is not an array".to_string()),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// `Data` of every thermostat message in `body`.
+    fn lcc_data(body: &str) -> Vec<Value> {
+        match parse_retrieve(body) {
+            RetrieveBody::Messages { thermostat, .. } => thermostat.iter().filter_map(|m| m.data().cloned()).collect(),
+            RetrieveBody::Empty => Vec::new(),
+            RetrieveBody::Malformed { reason, .. } => panic!("malformed: {reason}"),
+        }
+    }
+
     #[test]
     fn subscribe_message_structure() {
         let msg = subscribe_message("test_app");
@@ -188,17 +296,64 @@ mod tests {
     #[test]
     fn parse_retrieve_with_messages() {
         let body = r#"{"messages": [{"SenderID": "LCC", "Data": {"system": {"status": {"outdoorTemperature": 72}}}}]}"#;
-        let data = parse_retrieve_response(body);
+        let data = lcc_data(body);
         assert_eq!(data.len(), 1);
         assert_eq!(data[0]["system"]["status"]["outdoorTemperature"], 72);
     }
 
     #[test]
     fn parse_retrieve_empty() {
-        let data = parse_retrieve_response("");
+        let data = lcc_data("");
         assert!(data.is_empty());
     }
 
+    #[test]
+    fn parse_retrieve_rejects_malformed() {
+        for body in ["{\"messages\": [", "[]", r#"{"messages": {}}"#] {
+            match parse_retrieve(body) {
+                RetrieveBody::Malformed { body: b, .. } => assert_eq!(b, body),
+                other => panic!("{body}: expected malformed, got {other:?}"),
+            }
+        }
+        assert!(matches!(parse_retrieve("{}"), RetrieveBody::Empty));
+    }
+
+    #[test]
+    fn parse_retrieve_separates_senders() {
+        assert!(matches!(parse_retrieve(""), RetrieveBody::Empty));
+        assert!(matches!(parse_retrieve(r#"{"messages": null}"#), RetrieveBody::Empty));
+        let body = r#"{"messages": [
+            {"SenderID": "other", "Data": {}},
+            {"SenderID": "LCC", "Data": {"system": {}}}
+        ]}"#;
+        match parse_retrieve(body) {
+            RetrieveBody::Messages { thermostat, other } => {
+                assert_eq!(thermostat.len(), 1);
+                assert_eq!(other[0].sender_id.as_deref(), Some("other"));
+            }
+            other => panic!("expected messages, got {other:?}"),
+        }
+        let truncated = r#"{"messages": [{"SenderID": "LCC", "Da"#;
+        match parse_retrieve(truncated) {
+            RetrieveBody::Malformed { body, .. } => assert_eq!(body, truncated),
+            other => panic!("expected malformed, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn parse_retrieve_value_matches_text() {
+        let body = serde_json::json!({"messages": [{"SenderID": "LCC", "Data": {"system": {}}}]});
+        match parse_retrieve_value(&body) {
+            RetrieveBody::Messages { thermostat, .. } => assert_eq!(thermostat[0].data(), Some(&serde_json::json!({"system": {}}))),
+            other => panic!("expected messages, got {other:?}"),
+        }
+        assert!(matches!(parse_retrieve_value(&serde_json::json!({})), RetrieveBody::Empty));
+        match parse_retrieve_value(&serde_json::json!([])) {
+            RetrieveBody::Malformed { body, .. } => assert_eq!(body, "[]"),
+            other => panic!("expected malformed, got {other:?}"),
+        }
+    }
+
     #[test]
     fn parse_retrieve_filters_non_lcc() {
         let body = r#"{"messages": [
@@ -206,11 +361,47 @@ mod tests {
             {"SenderID": "mapp012345678901234567890", "Data": {"echo": true}},
             {"SenderID": "other", "Data": {"ignored": true}}
         ]}"#;
-        let data = parse_retrieve_response(body);
+        let data = lcc_data(body);
         assert_eq!(data.len(), 1);
         assert!(data[0].get("system").is_some());
     }
 
+    #[test]
+    fn parse_retrieve_keeps_every_sender() {
+        let body = r#"{"messages": [
+            {"SenderID": "LCC", "MessageType": "PropertyChange", "Data": {"system": {}}},
+            {"SenderId": "other", "MessageType": "Error", "MessageId": "m1", "Data": {"ignored": true}}
+        ]}"#;
+        let RetrieveBody::Messages { thermostat, other: msgs } = parse_retrieve(body) else {
+            panic!("expected messages");
+        };
+        assert_eq!(thermostat.len(), 1);
+        assert_eq!(msgs.len(), 1);
+        assert_eq!(msgs[0].sender_id.as_deref(), Some("other"));
+        assert_eq!(msgs[0].message_type.as_deref(), Some("Error"));
+        assert_eq!(msgs[0].message_id.as_deref(), Some("m1"));
+    }
+
+    #[test]
+    fn retrieved_message_references() {
+        let reply = RetrievedMessage::from_value(serde_json::json!({
+            "SenderID": "LCC", "MessageID": "reply-1", "MessageType": "CommandError",
+            "Data": {"error": {"requestId": "cmd-1"}}
+        }));
+        assert!(reply.references("cmd-1", "app"));
+        assert!(!reply.references("cmd-2", "app"));
+
+        let same_id = RetrievedMessage::from_value(serde_json::json!({
+            "SenderID": "LCC", "MessageID": "cmd-1", "MessageType": "CommandResponse"
+        }));
+        assert!(same_id.references("cmd-1", "app"));
+
+        let echo = RetrievedMessage::from_value(serde_json::json!({
+            "SenderID": "app", "MessageID": "cmd-1", "MessageType": "Command"
+        }));
+        assert!(!echo.references("cmd-1", "app"));
+    }
+
     #[test]
     fn set_manual_away_data_structure() {
         let data = set_manual_away_data(true);
@@ -240,8 +431,9 @@ mod tests {
 
     #[test]
     fn command_message_structure() {
-        let msg = command_message("test_app", serde_json::json!({"zones": []}));
+        let msg = command_message("test_app", "abc-123", serde_json::json!({"zones": []}));
         assert_eq!(msg["MessageType"], "Command");
+        assert_eq!(msg["MessageID"], "abc-123");
         assert_eq!(msg["SenderID"], "test_app");
         assert_eq!(msg["TargetID"], "LCC");
         assert!(msg["Data"]["zones"].is_array());
@@ -0,0 +1,228 @@
+use ring::digest::{digest, SHA256};
+use serde_json::Value;
+
+use crate::protocol::TARGET_LCC;
+
+/// What to do with a redacted value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RedactAction {
+    /// Replace with a short salted hash, so equal values stay equal.
+    Hash,
+    /// Replace strings with `"REDACTED"` and other values with `null`.
+    Mask,
+}
+
+/// Fields hidden from message log entries before they're written.
+///
+/// Paths are JSON pointers into each message (`/TargetID`,
+/// `/Data/system/config/name`); `*` matches every array element or object
+/// key. A path to an object or array redacts every value beneath it. Command
+/// entries are matched as `/Data/...`. Empty strings, `null`s and the
+/// thermostat's own `LCC` id are left alone so logs stay replayable.
+#[derive(Debug, Clone, Default)]
+pub struct RedactionPolicy {
+    rules: Vec<(String, RedactAction)>,
+    salt: Option<String>,
+}
+
+impl RedactionPolicy {
+    /// A policy with no rules.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Known sensitive fields: the account email in `TargetID`/`SenderID`,
+    /// smart-away device ids, serial numbers, the system name, registration
+    /// state and Wi-Fi details.
+    pub fn standard() -> Self {
+        Self::new()
+            .hash("/TargetID")
+            .hash("/SenderID")
+            .hash("/SenderId")
+            .hash("/Data/occupancy/smartAway/participants/*/deviceId")
+            .hash("/Data/occupancy/smartAway/update/deviceId")
+            .hash("/Data/system/ports/*/port/controllerSerialNumber")
+            .hash("/Data/interfaces/*/Info/status/macAddr")
+            .mask("/Data/system/config/name")
+            .mask("/Data/system/registration")
+            .mask("/Data/interfaces/*/Info/status/ssid")
+            .mask("/Data/interfaces/*/Info/status/ip")
+            .mask("/Data/interfaces/*/Info/status/router")
+    }
+
+    pub fn hash(self, pointer: impl Into<String>) -> Self {
+        self.rule(pointer, RedactAction::Hash)
+    }
+
+    pub fn mask(self, pointer: impl Into<String>) -> Self {
+        self.rule(pointer, RedactAction::Mask)
+    }
+
+    pub fn rule(mut self, pointer: impl Into<String>, action: RedactAction) -> Self {
+        self.rules.push((pointer.into(), action));
+        self
+    }
+
+    /// Salt for `Hash`. Without one, each logger picks a random salt, so
+    /// hashes match within a log but can't be looked up or compared across
+    /// logs. Set one to correlate logs, and keep it private.
+    pub fn salt(mut self, salt: impl Into<String>) -> Self {
+        self.salt = Some(salt.into());
+        self
+    }
+}
+
+/// A policy ready to apply, with its salt fixed.
+pub(crate) struct Redactor {
+    rules: Vec<(Vec<String>, RedactAction)>,
+    salt: String,
+}
+
+impl Redactor {
+    pub fn new(policy: RedactionPolicy) -> Self {
+        let rules = policy
+            .rules
+            .into_iter()
+            .map(|(pointer, action)| {
+                let segments = pointer
+                    .split('/')
+                    .skip(1)
+                    .map(|s| s.replace("~1", "/").replace("~0", "~"))
+                    .collect();
+                (segments, action)
+            })
+            .collect();
+        Self {
+            rules,
+            salt: policy.salt.unwrap_or_else(|| uuid::Uuid::new_v4().to_string()),
+        }
+    }
+
+    /// Redact one message in place.
+    pub fn message(&self, message: &mut Value) {
+        for (segments, action) in &self.rules {
+            self.apply(message, segments, *action);
+        }
+    }
+
+    /// Redact every message of a Retrieve body.
+    pub fn body(&self, body: &mut Value) {
+        if let Some(Value::Array(messages)) = body.get_mut("messages") {
+            for message in messages {
+                self.message(message);
+            }
+        }
+    }
+
+    /// Redact a command's `Data` payload.
+    pub fn data(&self, data: &mut Value) {
+        let mut message = serde_json::json!({ "Data": data.take() });
+        self.message(&mut message);
+        *data = message["Data"].take();
+    }
+
+    fn apply(&self, node: &mut Value, segments: &[String], action: RedactAction) {
+        let Some((first, rest)) = segments.split_first() else {
+            self.redact(node, action);
+            return;
+        };
+        match node {
+            Value::Object(map) if first == "*" => {
+                for child in map.values_mut() {
+                    self.apply(child, rest, action);
+                }
+            }
+            Value::Object(map) => {
+                if let Some(child) = map.get_mut(first) {
+                    self.apply(child, rest, action);
+                }
+            }
+            Value::Array(items) if first == "*" => {
+                for child in items {
+                    self.apply(child, rest, action);
+                }
+            }
+            Value::Array(items) => {
+                if let Some(child) = first.parse::<usize>().ok().and_then(|i| items.get_mut(i)) {
+                    self.apply(child, rest, action);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn redact(&self, node: &mut Value, action: RedactAction) {
+        match node {
+            Value::Null => {}
+            Value::String(s) if s.is_empty() || s == TARGET_LCC => {}
+            Value::Object(map) => map.values_mut().for_each(|v| self.redact(v, action)),
+            Value::Array(items) => items.iter_mut().for_each(|v| self.redact(v, action)),
+            _ => {
+                *node = match action {
+                    RedactAction::Hash => {
+                        let text = node.as_str().map(str::to_string).unwrap_or_else(|| node.to_string());
+                        Value::String(self.hash(&text))
+                    }
+                    RedactAction::Mask if node.is_string() => Value::String("REDACTED".to_string()),
+                    RedactAction::Mask => Value::Null,
+                };
+            }
+        }
+    }
+
+    fn hash(&self, value: &str) -> String {
+        let d = digest(&SHA256, format!("{}\0{value}", self.salt).as_bytes());
+        let hex: String = d.as_ref()[..6].iter().map(|b| format!("{b:02x}")).collect();
+        format!("redacted-{hex}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn standard_policy_hides_fixture_pii() {
+        let mut message: Value =
+            serde_json::from_str(include_str!("../tests/fixtures/system_heatpump_furnace.json")).unwrap();
+        Redactor::new(RedactionPolicy::standard()).message(&mut message);
+
+        let text = message.to_string();
+        assert!(!text.contains("myemail@email.com"));
+        assert!(!text.contains("53839044787040961695"));
+        assert!(!text.contains("West Moetown"));
+        assert_eq!(message.pointer("/Data/system/registration/state").unwrap(), "REDACTED");
+        assert_eq!(message.pointer("/Data/system/registration/statusMsg").unwrap(), "");
+        // Same device id in two places hashes the same.
+        assert_eq!(
+            message.pointer("/Data/occupancy/smartAway/participants/0/deviceId"),
+            message.pointer("/Data/occupancy/smartAway/update/deviceId"),
+        );
+    }
+
+    #[test]
+    fn hashes_are_salted() {
+        let policy = RedactionPolicy::new().hash("/TargetID");
+        let redact = |salt: &str| {
+            let mut m = json!({"TargetID": "mapp1_me@example.com", "SenderID": "LCC"});
+            Redactor::new(policy.clone().salt(salt)).message(&mut m);
+            m
+        };
+        assert_eq!(redact("a"), redact("a"));
+        assert_ne!(redact("a")["TargetID"], redact("b")["TargetID"]);
+        assert_eq!(redact("a")["SenderID"], "LCC");
+    }
+
+    #[test]
+    fn wildcards_and_masks() {
+        let redactor = Redactor::new(RedactionPolicy::new().mask("/Data/zones/*/config/name").mask("/Data/n"));
+        let mut data = json!({"zones": [{"config": {"name": "Nursery"}}, {"config": {"name": "Office"}}], "n": 5});
+        redactor.data(&mut data);
+        assert_eq!(data, json!({"zones": [{"config": {"name": "REDACTED"}}, {"config": {"name": "REDACTED"}}], "n": null}));
+
+        let mut body = json!({"messages": [{"TargetID": "x", "Data": {"n": 1}}]});
+        redactor.body(&mut body);
+        assert_eq!(body["messages"][0]["Data"]["n"], Value::Null);
+    }
+}
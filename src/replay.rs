@@ -0,0 +1,361 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde_json::{Map, Value};
+use tracing::warn;
+
+use crate::client::S30Client;
+use crate::sink::rotated_files;
+use crate::{Error, Result};
+
+/// One `dir: poll` entry from a message log, with its body reconstructed.
+#[derive(Debug, Clone)]
+pub struct RecordedPoll {
+    pub ts: DateTime<Utc>,
+    pub status: u16,
+    /// The full Retrieve body. `None` for 204s, bodies that weren't JSON and
+    /// diffed entries that couldn't be rebuilt.
+    pub body: Option<Value>,
+    /// Written by the logger with the full accumulated state, not received
+    /// from the thermostat.
+    pub keyframe: bool,
+}
+
+/// Feeds a recorded message log back through a client offline.
+///
+/// Reads logs in either `MessageLogMode`; `Diffed` entries are rebuilt into
+/// full bodies by applying their changes to the previous body. Keys the
+/// thermostat dropped between polls aren't recorded in a diffed log, so they
+/// linger in the rebuilt bodies. An unreadable line leaves the diffed entries
+/// after it without a body until the next full entry or keyframe.
+pub struct Replayer {
+    polls: Vec<RecordedPoll>,
+    speed: Option<f64>,
+}
+
+impl Replayer {
+    /// Open a log, decompressing it if it's gzip or zstd.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::from_reader(open_log(path.as_ref())?)
+    }
+
+    /// Open a rotated log: every rotated file of `path`, oldest first, then
+    /// `path` itself.
+    pub fn open_rotated(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self::from_polls(read_rotated(path.as_ref())?))
+    }
+
+    pub fn from_reader(reader: impl BufRead) -> Result<Self> {
+        Ok(Self::from_polls(read_polls(reader)?))
+    }
+
+    /// Replay polls built in code, e.g. from fixture files.
+    pub fn from_polls(polls: Vec<RecordedPoll>) -> Self {
+        Self {
+            polls,
+            speed: Some(1.0),
+        }
+    }
+
+    /// Replay `factor` times faster than recorded. Defaults to 1.0.
+    pub fn speed(mut self, factor: f64) -> Self {
+        self.speed = Some(factor);
+        self
+    }
+
+    /// Replay without waiting between polls.
+    pub fn instant(mut self) -> Self {
+        self.speed = None;
+        self
+    }
+
+    pub fn polls(&self) -> &[RecordedPoll] {
+        &self.polls
+    }
+
+    /// Feed every recorded body through `client` as if it had just been
+    /// polled, firing its event and snapshot callbacks. The client doesn't
+    /// need to be connected and nothing is sent. Returns the number of bodies
+    /// applied.
+    pub async fn run(&self, client: &mut S30Client) -> usize {
+        let mut applied = 0;
+        let mut previous: Option<DateTime<Utc>> = None;
+        for poll in &self.polls {
+            if let (Some(speed), Some(prev)) = (self.speed, previous)
+                && let Ok(gap) = (poll.ts - prev).to_std()
+                && speed > 0.0
+            {
+                tokio::time::sleep(Duration::from_secs_f64(gap.as_secs_f64() / speed)).await;
+            }
+            previous = Some(poll.ts);
+
+            let Some(body) = &poll.body else {
+                continue;
+            };
+            if let Err(e) = client.handle_value(body) {
+                warn!(ts = %poll.ts, error = %e, "skipping recorded body");
+                continue;
+            }
+            applied += 1;
+        }
+        applied
+    }
+}
+
+/// Poll entries of `path` and its rotated files, oldest first.
+pub(crate) fn read_rotated(path: &Path) -> Result<Vec<RecordedPoll>> {
+    let mut files = rotated_files(path)?;
+    files.push(path.to_path_buf());
+    let mut polls = Vec::new();
+    for file in files {
+        polls.extend(read_polls(open_log(&file)?)?);
+    }
+    Ok(polls)
+}
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// Open a message log for reading, decompressing by content rather than
+/// file name.
+pub(crate) fn open_log(path: &Path) -> Result<Box<dyn BufRead>> {
+    let mut file = BufReader::new(File::open(path)?);
+    let head = file.fill_buf()?;
+    if head.starts_with(GZIP_MAGIC) {
+        #[cfg(feature = "gzip")]
+        return Ok(Box::new(BufReader::new(UntilError::new(
+            flate2::bufread::MultiGzDecoder::new(file),
+        ))));
+        #[cfg(not(feature = "gzip"))]
+        return Err(compressed_without_feature("gzip"));
+    }
+    if head.starts_with(ZSTD_MAGIC) {
+        #[cfg(feature = "zstd")]
+        return Ok(Box::new(BufReader::new(UntilError::new(
+            zstd::stream::read::Decoder::with_buffer(file)?,
+        ))));
+        #[cfg(not(feature = "zstd"))]
+        return Err(compressed_without_feature("zstd"));
+    }
+    Ok(Box::new(file))
+}
+
+#[cfg(not(all(feature = "gzip", feature = "zstd")))]
+fn compressed_without_feature(format: &str) -> Error {
+    Error::InvalidLog {
+        line: 0,
+        reason: format!("{format}-compressed; build with the `{format}` feature"),
+    }
+}
+
+/// Ends a compressed stream at its first decode error. A log whose writer
+/// died mid-stream then reads up to its last flush instead of failing.
+#[cfg_attr(not(any(feature = "gzip", feature = "zstd")), allow(dead_code))]
+struct UntilError<R> {
+    inner: R,
+    done: bool,
+}
+
+#[cfg_attr(not(any(feature = "gzip", feature = "zstd")), allow(dead_code))]
+impl<R> UntilError<R> {
+    fn new(inner: R) -> Self {
+        Self { inner, done: false }
+    }
+}
+
+impl<R: Read> Read for UntilError<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.done {
+            return Ok(0);
+        }
+        match self.inner.read(buf) {
+            Err(e) if e.kind() != io::ErrorKind::Interrupted => {
+                warn!(error = %e, "compressed log ends early");
+                self.done = true;
+                Ok(0)
+            }
+            result => result,
+        }
+    }
+}
+
+/// Poll entries of a message log, in order, with diffed bodies rebuilt.
+///
+/// Lines that aren't JSON (a torn write, a damaged disk block) are skipped
+/// and break the diff chain: following diffed entries get no body until the
+/// next full entry or keyframe.
+pub(crate) fn read_polls(reader: impl BufRead) -> Result<Vec<RecordedPoll>> {
+    let mut polls = Vec::new();
+    let mut current: Option<Value> = None;
+
+    for (idx, line) in reader.lines().enumerate() {
+        let line_no = idx + 1;
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let invalid = |reason: String| Error::InvalidLog { line: line_no, reason };
+        let entry: Value = match serde_json::from_str(&line) {
+            Ok(entry) => entry,
+            Err(e) => {
+                warn!(line = line_no, error = %e, "skipping unreadable log line");
+                current = None;
+                continue;
+            }
+        };
+        if entry.get("dir").and_then(|v| v.as_str()) != Some("poll") {
+            continue;
+        }
+
+        let ts = entry
+            .get("ts")
+            .and_then(|v| v.as_str())
+            .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+            .ok_or_else(|| invalid("missing or invalid ts".to_string()))?
+            .with_timezone(&Utc);
+        let Some(status) = entry.get("status").and_then(|v| v.as_u64()) else {
+            // A poll that got no response has nothing to replay.
+            if entry.get("error").is_some() {
+                continue;
+            }
+            return Err(invalid("missing status".to_string()));
+        };
+        let status = status as u16;
+        let keyframe = entry.get("keyframe").and_then(|v| v.as_bool()).unwrap_or(false);
+
+        let body = if let Some(changes) = entry.get("changes") {
+            let Value::Array(changes) = changes else {
+                return Err(invalid("changes is not an array".to_string()));
+            };
+            match current.as_mut() {
+                Some(base) => {
+                    for change in changes {
+                        let path = change.get("path").and_then(|v| v.as_str()).unwrap_or("");
+                        set_path(base, path, change.get("new").cloned().unwrap_or(Value::Null));
+                    }
+                    Some(base.clone())
+                }
+                None => {
+                    warn!(line = line_no, "diffed entry without a full body to apply it to");
+                    None
+                }
+            }
+        } else if let Some(body) = entry.get("body").filter(|b| !b.is_null()) {
+            current = Some(body.clone());
+            Some(body.clone())
+        } else {
+            None
+        };
+
+        polls.push(RecordedPoll { ts, status, body, keyframe });
+    }
+    Ok(polls)
+}
+
+/// Set a dotted path as written by `diff_json`, creating objects on the way.
+fn set_path(root: &mut Value, path: &str, value: Value) {
+    if path.is_empty() {
+        *root = value;
+        return;
+    }
+    let mut node = root;
+    for key in path.split('.') {
+        if !node.is_object() {
+            *node = Value::Object(Map::new());
+        }
+        node = node
+            .as_object_mut()
+            .unwrap()
+            .entry(key)
+            .or_insert(Value::Null);
+    }
+    *node = value;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logger::{LogOptions, MessageLogMode, MessageLogger, RequestOutcome};
+    use serde_json::json;
+    use tempfile::NamedTempFile;
+
+    fn bodies(mode: MessageLogMode, polls: &[(u16, Value)]) -> Vec<RecordedPoll> {
+        let tmp = NamedTempFile::new().unwrap();
+        let path = tmp.path().to_str().unwrap();
+        let mut logger = MessageLogger::open(mode, path, LogOptions::default()).unwrap();
+        let outcome = |status| RequestOutcome {
+            sent_at: Utc::now(),
+            latency: Duration::ZERO,
+            status: Some(status),
+            error: None,
+        };
+        logger.log_command("set_mode", Some(0), "m1", &json!({}), &outcome(200));
+        for (status, body) in polls {
+            logger.log_poll(&outcome(*status), (*status == 200).then_some(body));
+        }
+        read_polls(BufReader::new(File::open(path).unwrap())).unwrap()
+    }
+
+    #[test]
+    fn rebuilds_diffed_bodies() {
+        let first = json!({"messages": [{"SenderID": "LCC", "Data": {"system": {"status": {"a": 1, "b": 2}}}}]});
+        let second = json!({"messages": [{"SenderID": "LCC", "Data": {"system": {"status": {"a": 3, "b": 2}}}}]});
+        let polls = [(200, first.clone()), (204, Value::Null), (200, second.clone()), (200, second.clone())];
+
+        let full = bodies(MessageLogMode::Full, &polls);
+        let diffed = bodies(MessageLogMode::Diffed, &polls);
+        assert_eq!(full.len(), 4);
+        for (f, d) in full.iter().zip(&diffed) {
+            assert_eq!(f.status, d.status);
+            assert_eq!(f.body, d.body);
+        }
+        assert_eq!(diffed[2].body.as_ref(), Some(&second));
+        assert!(diffed[1].body.is_none());
+    }
+
+    #[test]
+    fn set_path_creates_objects() {
+        let mut root = json!({"a": {"b": 1}});
+        set_path(&mut root, "a.c.d", json!(2));
+        set_path(&mut root, "a.b", json!([1]));
+        assert_eq!(root, json!({"a": {"b": [1], "c": {"d": 2}}}));
+    }
+
+    #[test]
+    fn failed_polls_have_no_body() {
+        let log = r#"{"ts":"2024-01-01T00:00:00Z","dir":"poll","status":null,"latency_ms":10000,"error":"timed out waiting for thermostat"}
+{"ts":"2024-01-01T00:00:01Z","dir":"poll","status":502,"latency_ms":3}
+{"ts":"2024-01-01T00:00:02Z","dir":"poll","status":500,"latency_ms":4,"error":"/Messages/app/Retrieve returned HTTP 500"}
+"#;
+        let polls = read_polls(log.as_bytes()).unwrap();
+        let statuses: Vec<u16> = polls.iter().map(|p| p.status).collect();
+        assert_eq!(statuses, [502, 500]);
+        assert!(polls.iter().all(|p| p.body.is_none()));
+    }
+
+    #[test]
+    fn diffed_entry_without_base_has_no_body() {
+        let log = r#"{"ts":"2024-01-01T00:00:00Z","dir":"poll","status":200,"changes":[]}"#;
+        let polls = read_polls(log.as_bytes()).unwrap();
+        assert_eq!(polls.len(), 1);
+        assert!(polls[0].body.is_none());
+    }
+
+    #[test]
+    fn damaged_line_breaks_chain_until_keyframe() {
+        let log = r#"{"ts":"2024-01-01T00:00:00Z","dir":"poll","status":200,"full":true,"body":{"a":1}}
+{"ts":"2024-01-01T00:01:00Z","dir":"poll","status":200,"chan
+{"ts":"2024-01-01T00:02:00Z","dir":"poll","status":200,"changes":[{"path":"a","old":2,"new":3}]}
+{"ts":"2024-01-01T00:03:00Z","dir":"poll","status":200,"full":true,"keyframe":true,"body":{"a":3}}
+{"ts":"2024-01-01T00:04:00Z","dir":"poll","status":200,"changes":[{"path":"a","old":3,"new":4}]}
+"#;
+        let polls = read_polls(log.as_bytes()).unwrap();
+        assert_eq!(polls.len(), 4);
+        assert!(polls[1].body.is_none());
+        assert!(polls[2].keyframe);
+        assert_eq!(polls[3].body, Some(json!({"a": 4})));
+    }
+}
@@ -0,0 +1,559 @@
+// In-process stand-in for the thermostat's LAN API: Connect, RequestData,
+// Retrieve long-poll, Publish and Disconnect, with one message queue per
+// app_id. Published commands are applied to the simulated state and echoed
+// to every subscriber the way the thermostat reports property changes.
+// Faults can be scripted to exercise the client's recovery paths.
+
+use std::collections::{HashMap, VecDeque};
+use std::convert::Infallible;
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper::body::Incoming;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use serde_json::{json, Map, Value};
+use tokio::net::TcpListener;
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+
+use crate::diff::merge_by_id;
+use crate::protocol::{new_message_id, TARGET_LCC};
+
+const DEFAULT_LONG_POLL: Duration = Duration::from_secs(15);
+/// Pause after a failed accept, so errors like EMFILE don't spin the loop.
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// A scripted misbehaviour, consumed by the next Retrieve it applies to.
+#[derive(Debug, Clone)]
+pub enum Fault {
+    /// Answer with this status instead of messages, e.g. 502.
+    Status(u16),
+    /// Hold the request this long, past the long-poll window, then answer 204.
+    Hang(Duration),
+    /// Forget the polling app's endpoint; this and later Retrieves get 404
+    /// until it connects again.
+    ForgetEndpoint,
+    /// Cut the next body carrying messages off halfway.
+    Truncate,
+    /// Deliver the next batch of queued messages newest first.
+    Reorder,
+}
+
+impl Fault {
+    /// Faults that alter a body rather than replace it wait for one to alter.
+    fn needs_messages(&self) -> bool {
+        matches!(self, Fault::Truncate | Fault::Reorder)
+    }
+}
+
+#[derive(Default)]
+struct SimState {
+    data: Value,
+    queues: HashMap<String, VecDeque<Value>>,
+    published: Vec<Value>,
+    faults: VecDeque<Fault>,
+    connects: u32,
+}
+
+struct Shared {
+    state: Mutex<SimState>,
+    notify: Notify,
+}
+
+impl Shared {
+    /// Queue a PropertyChange carrying `data` for every connected app.
+    fn broadcast(&self, state: &mut SimState, data: Value) {
+        for (app_id, queue) in state.queues.iter_mut() {
+            queue.push_back(property_change(app_id, data.clone()));
+        }
+        self.notify.notify_waiters();
+    }
+
+    /// Merge a change into the state, apply its side effects, and report the
+    /// touched sections to subscribers.
+    fn apply(&self, state: &mut SimState, data: &Value) {
+        let Value::Object(patch) = data else {
+            return;
+        };
+        let mut touched = Vec::new();
+        for (key, value) in patch {
+            if key == "systemControl" {
+                touched.extend(apply_system_control(&mut state.data, value));
+                continue;
+            }
+            merge_by_id(section(&mut state.data, key), value);
+            touched.push(key.clone());
+        }
+        if touched.iter().any(|k| k == "schedules" || k == "zones") {
+            sync_zone_periods(&mut state.data);
+            touched.push("zones".to_string());
+        }
+        touched.sort();
+        touched.dedup();
+
+        let mut echo = Map::new();
+        for key in touched {
+            if let Some(v) = state.data.get(&key) {
+                echo.insert(key, v.clone());
+            }
+        }
+        self.broadcast(state, Value::Object(echo));
+    }
+}
+
+/// Builder for [`Simulator`].
+#[derive(Default)]
+pub struct SimulatorBuilder {
+    bind: Option<SocketAddr>,
+    fixtures: Vec<PathBuf>,
+    seeds: Vec<Value>,
+}
+
+impl SimulatorBuilder {
+    /// Address to listen on. Defaults to an ephemeral port on 127.0.0.1.
+    pub fn bind(mut self, addr: SocketAddr) -> Self {
+        self.bind = Some(addr);
+        self
+    }
+
+    /// Seed state from a fixture file: a captured thermostat message (its
+    /// `Data` is used) or a bare data object. Later seeds merge over earlier ones.
+    pub fn fixture(mut self, path: impl Into<PathBuf>) -> Self {
+        self.fixtures.push(path.into());
+        self
+    }
+
+    /// Seed state from a JSON value, in the same shapes as `fixture`.
+    pub fn seed(mut self, data: Value) -> Self {
+        self.seeds.push(data);
+        self
+    }
+
+    pub async fn start(self) -> io::Result<Simulator> {
+        let mut data = Value::Object(Map::new());
+        for path in &self.fixtures {
+            let text = std::fs::read_to_string(path)?;
+            let value: Value = serde_json::from_str(&text)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {e}", path.display())))?;
+            merge_by_id(&mut data, value.get("Data").unwrap_or(&value));
+        }
+        for seed in &self.seeds {
+            merge_by_id(&mut data, seed.get("Data").unwrap_or(seed));
+        }
+        sync_zone_periods(&mut data);
+
+        let listener = TcpListener::bind(self.bind.unwrap_or(([127, 0, 0, 1], 0).into())).await?;
+        let addr = listener.local_addr()?;
+        let shared = Arc::new(Shared {
+            state: Mutex::new(SimState {
+                data,
+                ..Default::default()
+            }),
+            notify: Notify::new(),
+        });
+
+        let server = tokio::spawn(serve(listener, shared.clone()));
+        debug!(%addr, "simulator listening");
+        Ok(Simulator { addr, shared, server })
+    }
+}
+
+/// A simulated thermostat listening on a local port. Point a client at
+/// [`host`](Self::host) with `Protocol::Http`. Stops when dropped.
+pub struct Simulator {
+    addr: SocketAddr,
+    shared: Arc<Shared>,
+    server: JoinHandle<()>,
+}
+
+impl Simulator {
+    pub fn builder() -> SimulatorBuilder {
+        SimulatorBuilder::default()
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// `ip:port`, as `S30Client::builder` expects.
+    pub fn host(&self) -> String {
+        self.addr.to_string()
+    }
+
+    /// Current simulated state, as the thermostat would send it in full.
+    pub fn state(&self) -> Value {
+        self.shared.state.lock().unwrap().data.clone()
+    }
+
+    /// Apply a change originating from the thermostat, e.g. a new outdoor
+    /// temperature, and send it to every connected app.
+    pub fn update(&self, data: Value) {
+        let mut state = self.shared.state.lock().unwrap();
+        self.shared.apply(&mut state, &data);
+    }
+
+    /// Every Publish body received, oldest first.
+    pub fn published(&self) -> Vec<Value> {
+        self.shared.state.lock().unwrap().published.clone()
+    }
+
+    /// App ids with an open endpoint.
+    pub fn connected_apps(&self) -> Vec<String> {
+        let mut apps: Vec<_> = self.shared.state.lock().unwrap().queues.keys().cloned().collect();
+        apps.sort();
+        apps
+    }
+
+    /// Connect requests received, from any app.
+    pub fn connects(&self) -> u32 {
+        self.shared.state.lock().unwrap().connects
+    }
+
+    /// Queue a fault behind any already scripted.
+    pub fn inject(&self, fault: Fault) {
+        self.shared.state.lock().unwrap().faults.push_back(fault);
+        self.shared.notify.notify_waiters();
+    }
+
+    /// Answer the next `count` Retrieve requests with 502, as the thermostat
+    /// does when busy.
+    pub fn inject_502(&self, count: u32) {
+        for _ in 0..count {
+            self.inject(Fault::Status(502));
+        }
+    }
+
+    /// Drop `diagLevel` back to 0, as a thermostat reboot does.
+    pub fn reset_diag_level(&self) {
+        self.update(json!({"system": {"status": {"diagLevel": 0}}}));
+    }
+
+    /// Raise an alert, or mark it active again.
+    pub fn raise_alert(&self, code: u16) {
+        self.set_alert(code, true);
+    }
+
+    /// Mark an alert as no longer active.
+    pub fn clear_alert(&self, code: u16) {
+        self.set_alert(code, false);
+    }
+
+    fn set_alert(&self, code: u16, active: bool) {
+        let mut state = self.shared.state.lock().unwrap();
+        let existing = state.data.pointer("/alerts/active").and_then(|a| a.as_array());
+        let id = existing
+            .and_then(|a| {
+                a.iter()
+                    .find(|e| e.pointer("/alert/code").and_then(|v| v.as_u64()) == Some(code as u64))
+            })
+            .and_then(|e| e.get("id").cloned())
+            .unwrap_or_else(|| json!(existing.map_or(0, |a| a.len())));
+        let change = json!({"alerts": {"active": [
+            {"id": id, "alert": {"code": code, "isStillActive": active}}
+        ]}});
+        self.shared.apply(&mut state, &change);
+    }
+}
+
+impl Drop for Simulator {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+async fn serve(listener: TcpListener, shared: Arc<Shared>) {
+    loop {
+        let tcp = match listener.accept().await {
+            Ok((tcp, _)) => tcp,
+            Err(e) => {
+                warn!(error = %e, "simulator accept failed");
+                tokio::time::sleep(ACCEPT_RETRY_DELAY).await;
+                continue;
+            }
+        };
+        let shared = shared.clone();
+        tokio::spawn(async move {
+            let service = service_fn(move |req| handle(shared.clone(), req));
+            if let Err(e) = hyper::server::conn::http1::Builder::new()
+                .serve_connection(TokioIo::new(tcp), service)
+                .await
+            {
+                debug!(error = %e, "simulator connection ended");
+            }
+        });
+    }
+}
+
+async fn handle(shared: Arc<Shared>, req: Request<Incoming>) -> Result<Response<Full<Bytes>>, Infallible> {
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+    let long_poll = req
+        .uri()
+        .query()
+        .and_then(|q| q.split('&').find_map(|kv| kv.strip_prefix("LongPollingTimeout=")))
+        .and_then(|v| v.parse().ok())
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_LONG_POLL);
+    let body = match req.into_body().collect().await {
+        Ok(b) => b.to_bytes(),
+        Err(_) => return Ok(status(StatusCode::BAD_REQUEST)),
+    };
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+
+    let response = match (&method, segments.as_slice()) {
+        (&Method::POST, ["Endpoints", app_id, "Connect"]) => {
+            let mut state = shared.state.lock().unwrap();
+            state.connects += 1;
+            state.queues.entry(app_id.to_string()).or_default();
+            status(StatusCode::OK)
+        }
+        (&Method::POST, ["Endpoints", app_id, "Disconnect"]) => {
+            shared.state.lock().unwrap().queues.remove(*app_id);
+            shared.notify.notify_waiters();
+            status(StatusCode::OK)
+        }
+        (&Method::POST, ["Messages", "RequestData"]) => request_data(&shared, &body),
+        (&Method::POST, ["Messages", "Publish"]) => publish(&shared, &body),
+        (&Method::GET, ["Messages", app_id, "Retrieve"]) => retrieve(&shared, app_id, long_poll).await,
+        _ => status(StatusCode::NOT_FOUND),
+    };
+    Ok(response)
+}
+
+fn request_data(shared: &Shared, body: &[u8]) -> Response<Full<Bytes>> {
+    let Some(app_id) = sender_id(body) else {
+        return status(StatusCode::BAD_REQUEST);
+    };
+    let mut state = shared.state.lock().unwrap();
+    let full = property_change(&app_id, state.data.clone());
+    let Some(queue) = state.queues.get_mut(&app_id) else {
+        return status(StatusCode::NOT_FOUND);
+    };
+    queue.push_back(full);
+    shared.notify.notify_waiters();
+    status(StatusCode::OK)
+}
+
+fn publish(shared: &Shared, body: &[u8]) -> Response<Full<Bytes>> {
+    let Ok(msg) = serde_json::from_slice::<Value>(body) else {
+        return status(StatusCode::BAD_REQUEST);
+    };
+    let mut state = shared.state.lock().unwrap();
+    state.published.push(msg.clone());
+    if let Some(data) = msg.get("Data") {
+        shared.apply(&mut state, data);
+    }
+    status(StatusCode::OK)
+}
+
+/// What a Retrieve does next.
+enum Poll {
+    Respond(Response<Full<Bytes>>),
+    Hang(Duration),
+    Wait,
+}
+
+async fn retrieve(shared: &Shared, app_id: &str, long_poll: Duration) -> Response<Full<Bytes>> {
+    let deadline = tokio::time::Instant::now() + long_poll;
+    loop {
+        let notified = shared.notify.notified();
+        tokio::pin!(notified);
+        notified.as_mut().enable();
+        let poll = next_poll(&mut shared.state.lock().unwrap(), app_id);
+        match poll {
+            Poll::Respond(resp) => return resp,
+            Poll::Hang(d) => {
+                tokio::time::sleep(d).await;
+                return status(StatusCode::NO_CONTENT);
+            }
+            Poll::Wait => {}
+        }
+        if tokio::time::timeout_at(deadline, notified).await.is_err() {
+            return status(StatusCode::NO_CONTENT);
+        }
+    }
+}
+
+fn next_poll(state: &mut SimState, app_id: &str) -> Poll {
+    if state.faults.front().is_some_and(|f| !f.needs_messages()) {
+        match state.faults.pop_front() {
+            Some(Fault::Status(code)) => {
+                return Poll::Respond(status(StatusCode::from_u16(code).unwrap_or(StatusCode::BAD_GATEWAY)));
+            }
+            Some(Fault::Hang(d)) => return Poll::Hang(d),
+            Some(Fault::ForgetEndpoint) => {
+                debug!(app_id, "forgetting endpoint");
+                state.queues.remove(app_id);
+            }
+            _ => {}
+        }
+    }
+
+    let Some(queue) = state.queues.get_mut(app_id) else {
+        return Poll::Respond(status(StatusCode::NOT_FOUND));
+    };
+    if queue.is_empty() {
+        return Poll::Wait;
+    }
+    let mut messages: Vec<Value> = queue.drain(..).collect();
+
+    let fault = match state.faults.front() {
+        Some(f) if f.needs_messages() => state.faults.pop_front(),
+        _ => None,
+    };
+    if let Some(Fault::Reorder) = fault {
+        messages.reverse();
+    }
+    let mut body = json!({ "messages": messages }).to_string();
+    if let Some(Fault::Truncate) = fault {
+        let mut cut = body.len() / 2;
+        while !body.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        body.truncate(cut);
+    }
+    Poll::Respond(json_response(body))
+}
+
+fn sender_id(body: &[u8]) -> Option<String> {
+    let msg: Value = serde_json::from_slice(body).ok()?;
+    msg.get("SenderID")
+        .or_else(|| msg.get("SenderId"))
+        .and_then(|v| v.as_str())
+        .map(str::to_string)
+}
+
+fn property_change(app_id: &str, data: Value) -> Value {
+    json!({
+        "MessageId": new_message_id(),
+        "SenderID": TARGET_LCC,
+        "TargetID": app_id,
+        "MessageType": "PropertyChange",
+        "Data": data,
+    })
+}
+
+fn status(code: StatusCode) -> Response<Full<Bytes>> {
+    let mut resp = Response::new(Full::new(Bytes::new()));
+    *resp.status_mut() = code;
+    resp
+}
+
+fn json_response(body: String) -> Response<Full<Bytes>> {
+    let mut resp = Response::new(Full::new(Bytes::from(body)));
+    resp.headers_mut()
+        .insert(hyper::header::CONTENT_TYPE, "application/json".parse().unwrap());
+    resp
+}
+
+fn section<'a>(data: &'a mut Value, key: &str) -> &'a mut Value {
+    if !data.is_object() {
+        *data = Value::Object(Map::new());
+    }
+    data.as_object_mut()
+        .unwrap()
+        .entry(key)
+        .or_insert(Value::Null)
+}
+
+/// Copy each zone's current schedule period into `status.period`, which is
+/// where the thermostat reports the mode and setpoints in effect.
+fn sync_zone_periods(data: &mut Value) {
+    let schedules = data.get("schedules").cloned().unwrap_or(Value::Null);
+    let Some(Value::Array(zones)) = data.get_mut("zones") else {
+        return;
+    };
+    for zone in zones {
+        let Some(schedule_id) = zone.pointer("/config/scheduleId").and_then(|v| v.as_u64()) else {
+            continue;
+        };
+        let period = schedules
+            .as_array()
+            .and_then(|s| s.iter().find(|s| s.get("id").and_then(|v| v.as_u64()) == Some(schedule_id)))
+            .and_then(|s| s.pointer("/schedule/periods"))
+            .and_then(|p| p.as_array())
+            .and_then(|p| p.iter().find(|p| p.get("id").and_then(|v| v.as_u64()) == Some(0)))
+            .and_then(|p| p.get("period"))
+            .cloned();
+        if let Some(period) = period {
+            let status = section(zone, "status");
+            merge_by_id(section(status, "period"), &period);
+        }
+    }
+}
+
+/// Apply `systemControl` commands. Returns the state sections they changed.
+fn apply_system_control(data: &mut Value, control: &Value) -> Vec<String> {
+    let mut touched = Vec::new();
+    if let Some(level) = control.pointer("/diagControl/level") {
+        let system = section(data, "system");
+        *section(section(system, "status"), "diagLevel") = level.clone();
+        touched.push("system".to_string());
+    }
+    if let Some(update) = control.get("parameterUpdate") {
+        let et = update.get("et").and_then(|v| v.as_u64());
+        let pid = update.get("pid").and_then(|v| v.as_u64());
+        let value = update.get("value").cloned().unwrap_or(Value::Null);
+        if let Some(Value::Array(equipments)) = data.get_mut("equipments") {
+            for equipment in equipments {
+                if equipment.pointer("/equipment/equipType").and_then(|v| v.as_u64()) != et {
+                    continue;
+                }
+                let Some(Value::Array(params)) = equipment.pointer_mut("/equipment/parameters") else {
+                    continue;
+                };
+                for param in params {
+                    if param.pointer("/parameter/pid").and_then(|v| v.as_u64()) == pid
+                        && let Some(slot) = param.pointer_mut("/parameter/value")
+                    {
+                        *slot = value.clone();
+                    }
+                }
+            }
+            touched.push("equipments".to_string());
+        }
+    }
+    touched
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::manual_schedule_id;
+
+    #[test]
+    fn schedule_change_reaches_zone_status() {
+        let mut data = json!({
+            "zones": [{"id": 0, "config": {"scheduleId": 16}, "status": {"period": {"systemMode": "off"}}}],
+            "schedules": [{"id": 16, "schedule": {"periods": [{"id": 0, "period": {"systemMode": "off"}}]}}]
+        });
+        merge_by_id(
+            &mut data,
+            &crate::protocol::set_hvac_mode_data(manual_schedule_id(0), "heat"),
+        );
+        sync_zone_periods(&mut data);
+        assert_eq!(data.pointer("/zones/0/status/period/systemMode").unwrap(), "heat");
+    }
+
+    #[test]
+    fn parameter_update_sets_value() {
+        let mut data = json!({"equipments": [{"id": 1, "equipment": {
+            "equipType": 19,
+            "parameters": [{"id": 0, "parameter": {"pid": 72, "value": "1"}}]
+        }}]});
+        let touched = apply_system_control(
+            &mut data,
+            crate::protocol::set_parameter_data(19, 72, "0").get("systemControl").unwrap(),
+        );
+        assert_eq!(touched, vec!["equipments"]);
+        assert_eq!(data.pointer("/equipments/0/equipment/parameters/0/parameter/value").unwrap(), "0");
+    }
+}
@@ -0,0 +1,425 @@
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use tokio::sync::mpsc;
+
+/// Where message log entries go.
+///
+/// The logger decides what each entry holds (full or diffed bodies,
+/// keyframes, redaction, command tags) and hands the sink one line of JSON per
+/// entry, so every sink sees the NDJSON a log file would contain. Implement
+/// it to send entries to syslog, journald or anywhere else.
+pub trait MessageSink: Send {
+    /// Write one entry, without a trailing newline.
+    fn write_line(&mut self, line: &str) -> io::Result<()>;
+
+    /// Called before each entry. Start a new segment if one is due and return
+    /// `true`; the logger then writes a keyframe so the segment reads on its
+    /// own.
+    fn rotate_if_due(&mut self) -> io::Result<bool> {
+        Ok(false)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// How compressed message logs flush to disk. A crash loses at most this
+/// much; flushing more often costs compression.
+const COMPRESSED_FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Compression for the message log.
+///
+/// Each process start (and each rotated file) begins a new gzip member or
+/// zstd frame, and output is flushed every few seconds, so a crash only loses
+/// the tail. `Replayer` and `LogHistory` detect compressed logs by their
+/// contents, whatever the file is called.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogCompression {
+    #[default]
+    None,
+    #[cfg(feature = "gzip")]
+    Gzip,
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+/// The open log file, behind the chosen compression.
+enum LogFile {
+    Plain(File),
+    #[cfg(feature = "gzip")]
+    Gzip(flate2::write::GzEncoder<File>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::stream::AutoFinishEncoder<'static, File>),
+}
+
+impl LogFile {
+    fn open(path: &Path, compression: LogCompression) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(match compression {
+            LogCompression::None => LogFile::Plain(file),
+            #[cfg(feature = "gzip")]
+            LogCompression::Gzip => LogFile::Gzip(flate2::write::GzEncoder::new(file, flate2::Compression::default())),
+            #[cfg(feature = "zstd")]
+            LogCompression::Zstd => LogFile::Zstd(zstd::stream::Encoder::new(file, 0)?.auto_finish()),
+        })
+    }
+
+    fn inner(&self) -> &File {
+        match self {
+            LogFile::Plain(f) => f,
+            #[cfg(feature = "gzip")]
+            LogFile::Gzip(e) => e.get_ref(),
+            #[cfg(feature = "zstd")]
+            LogFile::Zstd(e) => e.get_ref(),
+        }
+    }
+
+    /// Bytes on disk, which for compressed logs trails what was written until
+    /// the next flush.
+    fn disk_len(&self) -> u64 {
+        self.inner().metadata().map(|m| m.len()).unwrap_or(0)
+    }
+
+    fn is_compressed(&self) -> bool {
+        !matches!(self, LogFile::Plain(_))
+    }
+}
+
+impl Write for LogFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            LogFile::Plain(f) => f.write(buf),
+            #[cfg(feature = "gzip")]
+            LogFile::Gzip(e) => e.write(buf),
+            #[cfg(feature = "zstd")]
+            LogFile::Zstd(e) => e.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            LogFile::Plain(f) => f.flush(),
+            #[cfg(feature = "gzip")]
+            LogFile::Gzip(e) => e.flush(),
+            #[cfg(feature = "zstd")]
+            LogFile::Zstd(e) => e.flush(),
+        }
+    }
+}
+
+/// When to start a new message log file, and how many old ones to keep.
+///
+/// Rotated files are renamed to `<path>.<UTC start time>`, e.g.
+/// `lennox.ndjson.20240115T030000.000Z`, and each new file opens with a
+/// keyframe holding the full accumulated thermostat state.
+#[derive(Debug, Clone)]
+pub struct LogRotation {
+    /// Rotate once the current file reaches this size.
+    pub max_bytes: Option<u64>,
+    /// Rotate on the first entry after UTC midnight.
+    pub daily: bool,
+    /// Rotated files to keep; older ones are deleted. `None` keeps them all.
+    pub max_files: Option<usize>,
+}
+
+impl Default for LogRotation {
+    fn default() -> Self {
+        Self {
+            max_bytes: Some(10 * 1024 * 1024),
+            daily: false,
+            max_files: Some(10),
+        }
+    }
+}
+
+/// The log file set up with `message_log`, rotated and compressed as asked.
+pub(crate) struct FileSink {
+    path: PathBuf,
+    file: LogFile,
+    compression: LogCompression,
+    rotation: Option<LogRotation>,
+    /// When the current file was started.
+    opened: DateTime<Utc>,
+    last_flush: Instant,
+}
+
+impl FileSink {
+    pub fn open(path: &Path, rotation: Option<LogRotation>, compression: LogCompression) -> io::Result<Self> {
+        let file = LogFile::open(path, compression)?;
+        let meta = file.inner().metadata()?;
+        // An existing file counts as started when it was last written, so a
+        // daily rotation still happens if the process restarts the next day.
+        let opened = match meta.modified() {
+            Ok(modified) if meta.len() > 0 => modified.into(),
+            _ => Utc::now(),
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            compression,
+            rotation,
+            opened,
+            last_flush: Instant::now(),
+        })
+    }
+}
+
+impl MessageSink for FileSink {
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        writeln!(self.file, "{line}")?;
+        if self.file.is_compressed() && self.last_flush.elapsed() >= COMPRESSED_FLUSH_INTERVAL {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn rotate_if_due(&mut self) -> io::Result<bool> {
+        let Some(rotation) = &self.rotation else {
+            return Ok(false);
+        };
+        let now = Utc::now();
+        let len = self.file.disk_len();
+        let full = rotation.max_bytes.is_some_and(|max| len >= max);
+        let new_day = rotation.daily && now.date_naive() != self.opened.date_naive();
+        if len == 0 || !(full || new_day) {
+            return Ok(false);
+        }
+
+        let mut rotated = rotated_path(&self.path, self.opened, 0);
+        for n in 1.. {
+            if !rotated.exists() {
+                break;
+            }
+            rotated = rotated_path(&self.path, self.opened, n);
+        }
+        fs::rename(&self.path, &rotated)?;
+        // Replacing the writer finishes the old stream into the renamed file.
+        self.file = LogFile::open(&self.path, self.compression)?;
+        self.opened = now;
+
+        if let Some(keep) = rotation.max_files {
+            let files = rotated_files(&self.path)?;
+            for old in &files[..files.len().saturating_sub(keep)] {
+                fs::remove_file(old)?;
+            }
+        }
+        Ok(true)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.last_flush = Instant::now();
+        self.file.flush()
+    }
+}
+
+fn rotated_path(path: &Path, started: DateTime<Utc>, n: u32) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(started.format(".%Y%m%dT%H%M%S%.3fZ").to_string());
+    if n > 0 {
+        name.push(format!("-{n}"));
+    }
+    path.with_file_name(name)
+}
+
+/// Rotated files of the log at `path`, oldest first. Doesn't include `path`.
+pub(crate) fn rotated_files(path: &Path) -> io::Result<Vec<PathBuf>> {
+    let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+        return Ok(Vec::new());
+    };
+    let dir = match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    };
+    let prefix = format!("{name}.");
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| {
+            p.file_name()
+                .and_then(|n| n.to_str())
+                .and_then(|n| n.strip_prefix(&prefix))
+                .is_some_and(is_rotation_stamp)
+        })
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+/// `20240115T030000...`: only files we rotated, never someone's `.bak`.
+fn is_rotation_stamp(s: &str) -> bool {
+    let b = s.as_bytes();
+    b.len() > 9 && b[..8].iter().all(u8::is_ascii_digit) && b[8] == b'T'
+}
+
+/// The most recent entries, kept in memory.
+///
+/// Holds the last `max_age` of traffic (at most `max_entries` lines) without
+/// touching disk, for dumping when something goes wrong. Clones share one
+/// buffer: give one to the builder and keep one to `dump`. A keyframe is
+/// written every quarter of `max_age`, so a dump of a diffed log replays from
+/// its first keyframe on.
+#[derive(Clone)]
+pub struct RingBufferSink {
+    ring: Arc<Mutex<Ring>>,
+}
+
+struct Ring {
+    entries: VecDeque<(Instant, String)>,
+    max_age: Duration,
+    max_entries: usize,
+    segment_started: Option<Instant>,
+}
+
+impl RingBufferSink {
+    pub fn new(max_age: Duration, max_entries: usize) -> Self {
+        Self {
+            ring: Arc::new(Mutex::new(Ring {
+                entries: VecDeque::new(),
+                max_age,
+                max_entries,
+                segment_started: None,
+            })),
+        }
+    }
+
+    /// Buffered entries, oldest first.
+    pub fn entries(&self) -> Vec<String> {
+        let mut ring = self.ring.lock().unwrap();
+        ring.evict();
+        ring.entries.iter().map(|(_, line)| line.clone()).collect()
+    }
+
+    /// Write the buffered entries as NDJSON, readable by `Replayer` and
+    /// `LogHistory`.
+    pub fn dump(&self, mut out: impl Write) -> io::Result<()> {
+        for line in self.entries() {
+            writeln!(out, "{line}")?;
+        }
+        out.flush()
+    }
+
+    pub fn clear(&self) {
+        self.ring.lock().unwrap().entries.clear();
+    }
+}
+
+impl Ring {
+    fn evict(&mut self) {
+        while self.entries.len() > self.max_entries {
+            self.entries.pop_front();
+        }
+        while self.entries.front().is_some_and(|(at, _)| at.elapsed() > self.max_age) {
+            self.entries.pop_front();
+        }
+    }
+}
+
+impl MessageSink for RingBufferSink {
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let mut ring = self.ring.lock().unwrap();
+        ring.entries.push_back((Instant::now(), line.to_string()));
+        ring.evict();
+        Ok(())
+    }
+
+    fn rotate_if_due(&mut self) -> io::Result<bool> {
+        let mut ring = self.ring.lock().unwrap();
+        let due = ring.segment_started.is_none_or(|at| at.elapsed() >= ring.max_age / 4);
+        if due {
+            ring.segment_started = Some(Instant::now());
+        }
+        Ok(due)
+    }
+}
+
+/// Entries written to any `io::Write`: stdout, a socket, a pipe to
+/// `logger(1)`.
+pub struct WriterSink<W> {
+    writer: W,
+}
+
+impl<W: Write + Send> WriterSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write + Send> MessageSink for WriterSink<W> {
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        writeln!(self.writer, "{line}")
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Each entry sent on a channel. Entries are dropped, with a warning, while
+/// the channel is full, so a slow receiver never stalls polling.
+impl MessageSink for mpsc::Sender<String> {
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        self.try_send(line.to_string()).map_err(|e| match e {
+            mpsc::error::TrySendError::Full(_) => io::Error::new(io::ErrorKind::WouldBlock, "log channel full"),
+            mpsc::error::TrySendError::Closed(_) => io::Error::new(io::ErrorKind::BrokenPipe, "log channel closed"),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ring_buffer_keeps_newest_entries() {
+        let sink = RingBufferSink::new(Duration::from_secs(3600), 3);
+        let mut writer = sink.clone();
+        for i in 0..5 {
+            writer.write_line(&format!("{{\"n\":{i}}}")).unwrap();
+        }
+        assert_eq!(sink.entries(), ["{\"n\":2}", "{\"n\":3}", "{\"n\":4}"]);
+
+        let mut out = Vec::new();
+        sink.dump(&mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "{\"n\":2}\n{\"n\":3}\n{\"n\":4}\n");
+        sink.clear();
+        assert!(sink.entries().is_empty());
+    }
+
+    #[test]
+    fn ring_buffer_drops_old_entries_and_requests_keyframes() {
+        let mut sink = RingBufferSink::new(Duration::from_millis(40), 100);
+        assert!(sink.rotate_if_due().unwrap());
+        assert!(!sink.rotate_if_due().unwrap());
+        sink.write_line("old").unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        sink.write_line("new").unwrap();
+        assert_eq!(sink.entries(), ["new"]);
+        assert!(sink.rotate_if_due().unwrap());
+    }
+
+    #[test]
+    fn writer_and_channel_sinks() {
+        let mut writer = WriterSink::new(Vec::new());
+        writer.write_line("a").unwrap();
+        writer.write_line("b").unwrap();
+        assert_eq!(writer.into_inner(), b"a\nb\n");
+
+        let (mut tx, mut rx) = mpsc::channel(1);
+        tx.write_line("a").unwrap();
+        assert_eq!(tx.write_line("b").unwrap_err().kind(), io::ErrorKind::WouldBlock);
+        assert_eq!(rx.try_recv().unwrap(), "a");
+        drop(rx);
+        assert_eq!(tx.write_line("c").unwrap_err().kind(), io::ErrorKind::BrokenPipe);
+    }
+}
@@ -0,0 +1,291 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use serde_json::Value;
+use tracing::debug;
+
+use crate::command::Publish;
+use crate::pinning::TofuVerifier;
+use crate::logger::{MessageLogger, RequestOutcome};
+use crate::protocol::{new_message_id, RetrievedMessage};
+use crate::types::*;
+use crate::{Error, Result};
+
+/// Published commands remembered for reply correlation.
+const MAX_PENDING_COMMANDS: usize = 64;
+
+struct PendingCommand {
+    seq: u64,
+    message_id: String,
+    action: String,
+    zone: Option<u8>,
+    outcome: Option<CommandOutcome>,
+}
+
+#[derive(Default)]
+struct CommandLog {
+    seq: u64,
+    pending: VecDeque<PendingCommand>,
+}
+
+/// The part of a client shared between the poller and every handle: HTTP,
+/// connection flag, message log and command tracking. Locks are never held
+/// across an await, so a publish never waits on a long poll.
+pub(crate) struct Transport {
+    http: reqwest::Client,
+    pub base_url: String,
+    pub app_id: String,
+    request_timeout: Duration,
+    long_poll_timeout: Duration,
+    connected: AtomicBool,
+    logger: Mutex<Option<MessageLogger>>,
+    commands: Mutex<CommandLog>,
+    health: Mutex<ConnectionHealth>,
+    pub tls: Option<Arc<TofuVerifier>>,
+}
+
+impl Transport {
+    pub fn new(
+        http: reqwest::Client,
+        base_url: String,
+        app_id: String,
+        request_timeout: Duration,
+        long_poll_timeout: Duration,
+        logger: Option<MessageLogger>,
+        tls: Option<Arc<TofuVerifier>>,
+    ) -> Self {
+        Self {
+            http,
+            base_url,
+            app_id,
+            request_timeout,
+            long_poll_timeout,
+            connected: AtomicBool::new(false),
+            logger: Mutex::new(logger),
+            commands: Mutex::new(CommandLog::default()),
+            health: Mutex::new(ConnectionHealth::default()),
+            tls,
+        }
+    }
+
+    pub fn post(&self, url: &str) -> reqwest::RequestBuilder {
+        self.http.post(url).timeout(self.request_timeout)
+    }
+
+    /// Retrieve request. The thermostat may hold it for the whole long-poll
+    /// window, so the request timeout applies on top of that.
+    pub fn retrieve(&self) -> reqwest::RequestBuilder {
+        let url = format!(
+            "{}/Messages/{}/Retrieve?LongPollingTimeout={}",
+            self.base_url,
+            self.app_id,
+            self.long_poll_timeout.as_secs()
+        );
+        self.http
+            .get(url)
+            .timeout(self.long_poll_timeout + self.request_timeout)
+    }
+
+    /// Send a request, turning a non-success status into `Error::Status`.
+    /// `endpoint` is the path reported in the error.
+    pub async fn send(&self, request: reqwest::RequestBuilder, endpoint: &str) -> Result<reqwest::Response> {
+        let resp = request.send().await.map_err(|e| self.check_tls(e.into()))?;
+        check_status(resp, endpoint).await
+    }
+
+    /// `send`, timed for the message log.
+    async fn send_timed(
+        &self,
+        request: reqwest::RequestBuilder,
+        endpoint: &str,
+    ) -> (Result<reqwest::Response>, RequestOutcome) {
+        let sent_at = Utc::now();
+        let started = Instant::now();
+        let result = self.send(request, endpoint).await;
+        let outcome = RequestOutcome::new(sent_at, started, &result);
+        (result, outcome)
+    }
+
+    /// POST `path`, with `body` as JSON if given, and log the request with
+    /// its status, latency and error.
+    pub async fn post_logged(&self, path: &str, body: Option<&Value>) -> Result<reqwest::Response> {
+        let mut request = self.post(&format!("{}{path}", self.base_url));
+        if let Some(body) = body {
+            request = request.json(body);
+        }
+        let (result, outcome) = self.send_timed(request, path).await;
+        self.log(|l| l.log_request("POST", path, body, &outcome));
+        result
+    }
+
+    /// Surface a pinned-certificate rejection, which reqwest reports as an
+    /// opaque connect error, as `Error::CertificateMismatch`.
+    pub fn check_tls(&self, err: Error) -> Error {
+        if let Error::Http(_) = err
+            && let Some(tls) = &self.tls
+            && let Some((expected, actual)) = tls.take_mismatch()
+        {
+            return Error::CertificateMismatch {
+                host: tls.host().to_string(),
+                expected,
+                actual,
+            };
+        }
+        err
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Acquire)
+    }
+
+    pub fn set_connected(&self, connected: bool) {
+        self.connected.store(connected, Ordering::Release);
+    }
+
+    pub fn health(&self) -> ConnectionHealth {
+        self.health.lock().unwrap().clone()
+    }
+
+    /// Apply `f` to the health record. Returns the state transition, if any.
+    pub fn update_health(
+        &self,
+        f: impl FnOnce(&mut ConnectionHealth),
+    ) -> Option<(ConnectionState, ConnectionState)> {
+        let mut health = self.health.lock().unwrap();
+        let from = health.state;
+        f(&mut health);
+        (health.state != from).then_some((from, health.state))
+    }
+
+    pub fn log(&self, f: impl FnOnce(&mut MessageLogger)) {
+        if let Some(logger) = self.logger.lock().unwrap().as_mut() {
+            f(logger);
+        }
+    }
+
+    /// Publish one command and record it for reply correlation. Returns its MessageID.
+    pub async fn publish(&self, action: &str, zone: Option<u8>, data: Value) -> Result<String> {
+        if !self.is_connected() {
+            return Err(Error::NotConnected);
+        }
+        self.publish_message(action, zone, data).await
+    }
+
+    /// `publish` without the connected check, for commands sent while
+    /// subscribing.
+    pub async fn publish_message(&self, action: &str, zone: Option<u8>, data: Value) -> Result<String> {
+        let message_id = new_message_id();
+        {
+            let mut commands = self.commands.lock().unwrap();
+            commands.seq += 1;
+            let seq = commands.seq;
+            if commands.pending.len() == MAX_PENDING_COMMANDS {
+                commands.pending.pop_front();
+            }
+            commands.pending.push_back(PendingCommand {
+                seq,
+                message_id: message_id.clone(),
+                action: action.to_string(),
+                zone,
+                outcome: None,
+            });
+        }
+
+        let msg = crate::protocol::command_message(&self.app_id, &message_id, data);
+        let url = format!("{}/Messages/Publish", self.base_url);
+        let (result, outcome) = self.send_timed(self.post(&url).json(&msg), "/Messages/Publish").await;
+        self.log(|l| l.log_command(action, zone, &message_id, &msg["Data"], &outcome));
+        result?;
+        Ok(message_id)
+    }
+
+    /// Publish a planned command in order, stopping at the first failure.
+    pub async fn publish_all(&self, plan: Vec<Publish>) -> Result<()> {
+        for step in plan {
+            self.publish(step.action, step.zone, step.data).await?;
+        }
+        Ok(())
+    }
+
+    /// Sequence number of the most recently published command.
+    pub fn command_seq(&self) -> u64 {
+        self.commands.lock().unwrap().seq
+    }
+
+    pub fn command_ids_since(&self, seq: u64) -> Vec<String> {
+        self.commands
+            .lock()
+            .unwrap()
+            .pending
+            .iter()
+            .filter(|p| p.seq > seq)
+            .map(|p| p.message_id.clone())
+            .collect()
+    }
+
+    pub fn last_command_id(&self) -> Option<String> {
+        self.commands
+            .lock()
+            .unwrap()
+            .pending
+            .back()
+            .map(|p| p.message_id.clone())
+    }
+
+    pub fn command_outcome(&self, message_id: &str) -> Option<CommandOutcome> {
+        self.commands
+            .lock()
+            .unwrap()
+            .pending
+            .iter()
+            .find(|p| p.message_id == message_id)
+            .and_then(|p| p.outcome.clone())
+    }
+
+    /// Match a thermostat message against recently published commands.
+    pub fn correlate_reply(&self, msg: &RetrievedMessage) -> Option<Event> {
+        let mut commands = self.commands.lock().unwrap();
+        let pending = commands
+            .pending
+            .iter_mut()
+            .find(|p| msg.references(&p.message_id, &self.app_id))?;
+        let outcome = CommandOutcome {
+            message_id: pending.message_id.clone(),
+            action: pending.action.clone(),
+            zone: pending.zone,
+            sender_id: msg.sender_id.clone(),
+            message_type: msg.message_type.clone(),
+            reply: msg.raw.clone(),
+        };
+        debug!(
+            message_id = %outcome.message_id,
+            action = %outcome.action,
+            message_type = ?outcome.message_type,
+            "command reply"
+        );
+        pending.outcome = Some(outcome.clone());
+        Some(Event::CommandOutcome { outcome })
+    }
+}
+
+/// `Error::Status` with the response body for anything but a 2xx.
+pub(crate) async fn check_status(resp: reqwest::Response, endpoint: &str) -> Result<reqwest::Response> {
+    if resp.status().is_success() {
+        return Ok(resp);
+    }
+    Err(status_error(resp, endpoint).await)
+}
+
+/// `Error::Status` for a response already known to be a failure.
+pub(crate) async fn status_error(resp: reqwest::Response, endpoint: &str) -> Error {
+    let status = resp.status().as_u16();
+    let body = resp.text().await.unwrap_or_default();
+    Error::Status {
+        endpoint: endpoint.to_string(),
+        status,
+        body,
+    }
+}
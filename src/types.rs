@@ -1,5 +1,10 @@
 use std::collections::BTreeMap;
 use std::fmt;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
 /// Temperature stored as Celsius internally.
 /// Handles Lennox rounding: F to whole degrees, C to 0.5 increments.
@@ -138,6 +143,8 @@ pub struct Zone {
     pub aux_heat: bool,
     pub schedule_id: Option<u32>,
     pub override_active: bool,
+    /// When the thermostat last reported anything for this zone.
+    pub last_updated: Option<DateTime<Utc>>,
 }
 
 impl Zone {
@@ -147,14 +154,15 @@ impl Zone {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
 pub enum Descriptor {
     Range { min: f64, max: f64, inc: f64, unit: String },
     Radio { options: BTreeMap<String, String> },
     String { max_len: Option<u32> },
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Parameter {
     pub pid: u16,
     pub name: String,
@@ -168,6 +176,7 @@ pub struct Equipment {
     pub id: u16,
     pub equip_type: u16,
     pub parameters: BTreeMap<u16, Parameter>,
+    pub last_updated: Option<DateTime<Utc>>,
 }
 
 impl Equipment {
@@ -214,6 +223,8 @@ pub struct System {
     pub diag_level: Option<u8>,
     pub hp_low_ambient_lockout: bool,
     pub aux_heat_high_ambient_lockout: bool,
+    /// When the thermostat last reported anything for this system, its zones or equipment.
+    pub last_updated: Option<DateTime<Utc>>,
 }
 
 impl System {
@@ -228,6 +239,12 @@ impl System {
                 && matches!(self.smart_away_setpoint_state.as_str(), "transition" | "away"))
     }
 
+    /// True if nothing has been reported within `max_age`, or ever.
+    pub fn is_stale(&self, max_age: Duration) -> bool {
+        self.last_updated
+            .is_none_or(|t| (Utc::now() - t).to_std().is_ok_and(|age| age > max_age))
+    }
+
     pub fn equipment(&self, id: u16) -> Option<&Equipment> {
         self.equipments.iter().find(|e| e.id == id)
     }
@@ -241,6 +258,78 @@ impl System {
     }
 }
 
+/// A thermostat message that references a command this client published.
+#[derive(Debug, Clone)]
+pub struct CommandOutcome {
+    pub message_id: String,
+    pub action: String,
+    pub zone: Option<u8>,
+    pub sender_id: Option<String>,
+    pub message_type: Option<String>,
+    /// The full reply message as received.
+    pub reply: Value,
+}
+
+impl CommandOutcome {
+    /// Best-effort: the reply's MessageType or payload mentions an error.
+    pub fn is_error(&self) -> bool {
+        let type_says_error = self.message_type.as_deref().is_some_and(|t| {
+            let t = t.to_ascii_lowercase();
+            t.contains("error") || t.contains("fail") || t.contains("reject")
+        });
+        let body_says_error = ["error", "Error", "errorCode"].iter().any(|k| {
+            self.reply.get(k).is_some()
+                || self.reply.get("Data").and_then(|d| d.get(k)).is_some()
+        });
+        type_says_error || body_says_error
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConnectionState {
+    /// Never connected, or `disconnect()` was called.
+    #[default]
+    Disconnected,
+    /// First connect/subscribe in progress.
+    Connecting,
+    /// Subscribed and the last poll succeeded.
+    Subscribed,
+    /// Subscribed, but the last poll failed or returned a 502.
+    Degraded,
+    /// Subscription lost; re-connecting.
+    Reconnecting,
+    /// The driver hit an error that reconnecting won't fix and stopped.
+    Failed,
+}
+
+/// Connection state plus the timing behind it, for health checks.
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionHealth {
+    pub state: ConnectionState,
+    /// Last poll that returned 200 or 204.
+    pub last_successful_poll: Option<DateTime<Utc>>,
+    /// Last poll that carried thermostat data.
+    pub last_data: Option<DateTime<Utc>>,
+    /// Failed connects and polls since the last success.
+    pub consecutive_failures: u32,
+    /// Poll bodies that could not be parsed, since the client was built.
+    pub malformed_responses: u64,
+    /// Messages from senders other than the thermostat that weren't replies
+    /// to our commands, since the client was built.
+    pub unexpected_messages: u64,
+}
+
+impl ConnectionHealth {
+    pub fn time_since_last_data(&self) -> Option<Duration> {
+        self.last_data.and_then(|t| (Utc::now() - t).to_std().ok())
+    }
+
+    pub fn time_since_last_poll(&self) -> Option<Duration> {
+        self.last_successful_poll
+            .and_then(|t| (Utc::now() - t).to_std().ok())
+    }
+}
+
 /// Events emitted by the diff engine when state changes.
 #[derive(Debug, Clone)]
 pub enum Event {
@@ -272,4 +361,18 @@ pub enum Event {
     HpLockoutChanged { locked_out: bool },
     AuxLockoutChanged { locked_out: bool },
     AlertChanged { code: u16, active: bool },
+    /// A thermostat reply or error that references a published command.
+    CommandOutcome { outcome: CommandOutcome },
+    /// A `subscribe()` stream fell behind and skipped `missed` events.
+    Lagged { missed: u64 },
+    ConnectionChanged { from: ConnectionState, to: ConnectionState },
+    /// No data has arrived for `idle`, although polls may still be succeeding.
+    /// Fired once per stale period.
+    DataStale { idle: Duration },
+    /// A poll body that could not be parsed. The poll also fails with
+    /// `Error::MalformedResponse`.
+    MalformedResponse { body: String, reason: String },
+    /// A message from a sender other than the thermostat that isn't a reply
+    /// to one of our commands.
+    UnexpectedMessage { sender_id: Option<String>, message_type: Option<String>, raw: Value },
 }
@@ -1,6 +1,10 @@
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use lennox_s30::{Event, S30Client};
+use lennox_s30::{
+    Confirmation, ConnectionState, Event, ParamKey, ParameterBackup, Protocol, ReconnectPolicy,
+    RestoreAction, S30Client, S30Handle,
+};
 use wiremock::matchers::{body_string_contains, method, path_regex};
 use wiremock::{Mock, MockServer, ResponseTemplate};
 
@@ -21,7 +25,7 @@ async fn connected_client(server: &MockServer) -> S30Client {
     }
     let addr = server.address();
     let mut client = S30Client::builder(format!("{}:{}", addr.ip(), addr.port()))
-        .protocol("http")
+        .protocol(Protocol::Http)
         .build();
     client.connect().await.expect("connect should succeed");
     client
@@ -45,7 +49,7 @@ async fn connect_sends_to_correct_endpoints() {
 
     let addr = server.address();
     let mut client = S30Client::builder(format!("{}:{}", addr.ip(), addr.port()))
-        .protocol("http")
+        .protocol(Protocol::Http)
         .build();
     client.connect().await.expect("connect should succeed");
 }
@@ -93,7 +97,7 @@ async fn poll_fires_events_on_state_change() {
         mock.mount(&server).await;
     }
     let mut client = S30Client::builder(format!("{}:{}", addr.ip(), addr.port()))
-        .protocol("http")
+        .protocol(Protocol::Http)
         .on_event(move |event| {
             events_clone.lock().unwrap().push(format!("{event:?}"));
         })
@@ -107,7 +111,7 @@ async fn poll_fires_events_on_state_change() {
 
 #[tokio::test]
 async fn poll_not_connected_returns_error() {
-    let client_builder = S30Client::builder("127.0.0.1:9999").protocol("http");
+    let client_builder = S30Client::builder("127.0.0.1:9999").protocol(Protocol::Http);
     let mut client = client_builder.build();
     let err = client.poll().await.unwrap_err();
     assert!(
@@ -235,7 +239,7 @@ async fn snapshot_callback_fires_after_events() {
         mock.mount(&server).await;
     }
     let mut client = S30Client::builder(format!("{}:{}", addr.ip(), addr.port()))
-        .protocol("http")
+        .protocol(Protocol::Http)
         .on_snapshot(move |system| {
             if let Some(temp) = system.outdoor_temperature {
                 temps_clone.lock().unwrap().push(temp.celsius());
@@ -297,7 +301,7 @@ async fn second_poll_only_fires_events_for_changes() {
         mock.mount(&server).await;
     }
     let mut client = S30Client::builder(format!("{}:{}", addr.ip(), addr.port()))
-        .protocol("http")
+        .protocol(Protocol::Http)
         .on_event(move |event| {
             events_clone.lock().unwrap().push(format!("{event:?}"));
         })
@@ -420,7 +424,7 @@ async fn poll_occupancy_fires_away_event() {
         mock.mount(&server).await;
     }
     let mut client = S30Client::builder(format!("{}:{}", addr.ip(), addr.port()))
-        .protocol("http")
+        .protocol(Protocol::Http)
         .on_event(move |event| {
             events_clone.lock().unwrap().push(event.clone());
         })
@@ -500,7 +504,7 @@ async fn poll_schedule_hold_fires_event() {
         mock.mount(&server).await;
     }
     let mut client = S30Client::builder(format!("{}:{}", addr.ip(), addr.port()))
-        .protocol("http")
+        .protocol(Protocol::Http)
         .on_event(move |event| {
             events_clone.lock().unwrap().push(event.clone());
         })
@@ -642,7 +646,7 @@ async fn poll_parses_alerts_and_fires_lockout_events() {
         mock.mount(&server).await;
     }
     let mut client = S30Client::builder(format!("{}:{}", addr.ip(), addr.port()))
-        .protocol("http")
+        .protocol(Protocol::Http)
         .on_event(move |event| {
             events_clone.lock().unwrap().push(event.clone());
         })
@@ -704,7 +708,7 @@ async fn parameter_change_fires_event() {
         mock.mount(&server).await;
     }
     let mut client = S30Client::builder(format!("{}:{}", addr.ip(), addr.port()))
-        .protocol("http")
+        .protocol(Protocol::Http)
         .on_event(move |event| {
             events_clone.lock().unwrap().push(event.clone());
         })
@@ -825,3 +829,1084 @@ async fn set_equipment_parameter_rejects_disabled() {
         .unwrap_err();
     assert!(matches!(err, lennox_s30::Error::InvalidParameter { .. }));
 }
+
+#[tokio::test]
+async fn set_parameter_typed_converts_temperature() {
+    let server = MockServer::start().await;
+    let poll_body = serde_json::json!({
+        "messages": [{"SenderID": "LCC", "Data": {
+            "equipments": [{"id": 1, "equipment": {
+                "equipType": 19,
+                "parameters": [{"id": 0, "parameter": {
+                    "pid": 128, "name": "High Balance Point", "value": "50",
+                    "enabled": true, "descriptor": "range",
+                    "range": {"min": "-17", "max": "75", "inc": "1"}, "unit": "F"
+                }}]
+            }}]
+        }}]
+    });
+    Mock::given(method("GET"))
+        .and(path_regex(r"/Messages/.+/Retrieve"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&poll_body))
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path_regex(r"/Messages/Publish"))
+        .and(body_string_contains(r#""value":"45""#))
+        .respond_with(ResponseTemplate::new(200).set_body_string("{}"))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let mut client = connected_client(&server).await;
+    client.poll().await.unwrap();
+
+    let equip = client.systems()[0].equipment(1).unwrap();
+    let current: lennox_s30::Temperature = equip.get(ParamKey::HIGH_BALANCE_POINT).unwrap();
+    assert_eq!(current.to_lennox_fahrenheit(), 50);
+
+    client
+        .set_parameter_typed(
+            1,
+            ParamKey::HIGH_BALANCE_POINT,
+            lennox_s30::Temperature::from_fahrenheit(45.0),
+        )
+        .await
+        .expect("typed write should succeed");
+
+    let err = client
+        .set_parameter_typed(1, ParamKey::new(7, 128), 45.0)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, lennox_s30::Error::InvalidParameter { .. }));
+}
+
+#[tokio::test]
+async fn restore_parameters_publishes_only_differences() {
+    let server = MockServer::start().await;
+    let poll_body = serde_json::json!({
+        "messages": [{"SenderID": "LCC", "Data": {
+            "equipments": [{"id": 1, "equipment": {
+                "equipType": 19,
+                "parameters": [{"id": 0, "parameter": {
+                    "pid": 304, "name": "HP Lockout Time", "value": "60",
+                    "enabled": true, "descriptor": "range",
+                    "range": {"min": "60", "max": "240", "inc": "30"}, "unit": "min"
+                }}, {"id": 1, "parameter": {
+                    "pid": 128, "name": "High Balance Point", "value": "50",
+                    "enabled": true, "descriptor": "range",
+                    "range": {"min": "-17", "max": "75", "inc": "1"}, "unit": "F"
+                }}]
+            }}]
+        }}]
+    });
+    Mock::given(method("GET"))
+        .and(path_regex(r"/Messages/.+/Retrieve"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&poll_body))
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path_regex(r"/Messages/Publish"))
+        .and(body_string_contains(r#""pid":304"#))
+        .respond_with(ResponseTemplate::new(200).set_body_string("{}"))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let mut client = connected_client(&server).await;
+    client.poll().await.unwrap();
+
+    let tmp = tempfile::NamedTempFile::new().unwrap();
+    let mut backup = client.export_parameters();
+    for p in &mut backup.equipments[0].parameters {
+        if p.pid == 304 {
+            p.value = "120".to_string();
+        }
+    }
+    backup.save(tmp.path()).unwrap();
+
+    let backup = ParameterBackup::load(tmp.path()).unwrap();
+    let dry_run = backup.plan(client.systems());
+    assert_eq!(dry_run.changes().count(), 1);
+
+    let report = client.restore_parameters(&backup).await.unwrap();
+    let applied: Vec<_> = report
+        .entries
+        .iter()
+        .filter(|e| e.action == RestoreAction::Applied)
+        .map(|e| e.pid)
+        .collect();
+    assert_eq!(applied, vec![304]);
+}
+
+fn hp_lockout_body(value: &str) -> serde_json::Value {
+    serde_json::json!({
+        "messages": [{"SenderID": "LCC", "Data": {
+            "equipments": [{"id": 1, "equipment": {
+                "equipType": 19,
+                "parameters": [{"id": 0, "parameter": {
+                    "pid": 304, "name": "HP Lockout Time", "value": value,
+                    "enabled": true, "descriptor": "range",
+                    "range": {"min": "60", "max": "240", "inc": "30"}, "unit": "min"
+                }}]
+            }}]
+        }}]
+    })
+}
+
+async fn client_with_hp_lockout(server: &MockServer, followup: ResponseTemplate) -> S30Client {
+    Mock::given(method("GET"))
+        .and(path_regex(r"/Messages/.+/Retrieve"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(hp_lockout_body("60")))
+        .up_to_n_times(1)
+        .mount(server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path_regex(r"/Messages/Publish"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("{}"))
+        .mount(server)
+        .await;
+
+    let mut client = connected_client(server).await;
+    client.poll().await.unwrap();
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"/Messages/.+/Retrieve"))
+        .respond_with(followup)
+        .mount(server)
+        .await;
+    client
+}
+
+#[tokio::test]
+async fn set_equipment_parameter_confirmed_returns_value() {
+    let server = MockServer::start().await;
+    let followup = ResponseTemplate::new(200).set_body_json(hp_lockout_body("90"));
+    let mut client = client_with_hp_lockout(&server, followup).await;
+
+    let confirmed = client
+        .set_equipment_parameter_confirmed(1, 304, "90", Duration::from_secs(5))
+        .await
+        .expect("thermostat should confirm");
+    assert_eq!(confirmed, "90");
+}
+
+#[tokio::test]
+async fn set_equipment_parameter_confirmed_detects_rejection() {
+    let server = MockServer::start().await;
+    let followup = ResponseTemplate::new(200).set_body_json(hp_lockout_body("120"));
+    let mut client = client_with_hp_lockout(&server, followup).await;
+
+    let err = client
+        .set_equipment_parameter_confirmed(1, 304, "90", Duration::from_secs(5))
+        .await
+        .unwrap_err();
+    match err {
+        lennox_s30::Error::ParameterRejected { requested, actual, .. } => {
+            assert_eq!(requested, "90");
+            assert_eq!(actual, "120");
+        }
+        other => panic!("expected ParameterRejected, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn set_equipment_parameter_confirmed_detects_old_value_reported_back() {
+    let server = MockServer::start().await;
+    let followup = ResponseTemplate::new(200).set_body_json(hp_lockout_body("60"));
+    let mut client = client_with_hp_lockout(&server, followup).await;
+
+    let err = client
+        .set_equipment_parameter_confirmed(1, 304, "90", Duration::from_secs(5))
+        .await
+        .unwrap_err();
+    match err {
+        lennox_s30::Error::ParameterRejected { requested, actual, .. } => {
+            assert_eq!(requested, "90");
+            assert_eq!(actual, "60");
+        }
+        other => panic!("expected ParameterRejected, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn set_equipment_parameter_confirmed_times_out() {
+    let server = MockServer::start().await;
+    let followup = ResponseTemplate::new(204);
+    let mut client = client_with_hp_lockout(&server, followup).await;
+
+    let err = client
+        .set_equipment_parameter_confirmed(1, 304, "90", Duration::from_millis(50))
+        .await
+        .unwrap_err();
+    assert!(matches!(err, lennox_s30::Error::ParameterUnconfirmed { .. }), "got {err:?}");
+    assert!(!err.is_retryable(), "a retry could re-send a write that already applied");
+}
+
+#[tokio::test]
+async fn set_equipment_parameter_confirmed_cuts_long_poll_short() {
+    let server = MockServer::start().await;
+    let followup = ResponseTemplate::new(204).set_delay(Duration::from_secs(5));
+    let mut client = client_with_hp_lockout(&server, followup).await;
+
+    let started = std::time::Instant::now();
+    let err = client
+        .set_equipment_parameter_confirmed(1, 304, "90", Duration::from_millis(200))
+        .await
+        .unwrap_err();
+    assert!(matches!(err, lennox_s30::Error::ParameterUnconfirmed { .. }), "got {err:?}");
+    assert!(started.elapsed() < Duration::from_secs(2));
+}
+
+fn zone_mode_body(mode: &str) -> serde_json::Value {
+    serde_json::json!({
+        "messages": [{"SenderID": "LCC", "Data": {
+            "zones": [{"id": 0, "status": {"period": {"systemMode": mode}}}]
+        }}]
+    })
+}
+
+async fn mount_publish_and_followup(server: &MockServer, followup: ResponseTemplate) {
+    Mock::given(method("POST"))
+        .and(path_regex(r"/Messages/Publish"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("{}"))
+        .mount(server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path_regex(r"/Messages/.+/Retrieve"))
+        .respond_with(followup)
+        .mount(server)
+        .await;
+}
+
+#[tokio::test]
+async fn set_hvac_mode_and_confirm_confirmed() {
+    let server = MockServer::start().await;
+    let mut client = client_with_zone(&server).await;
+    mount_publish_and_followup(
+        &server,
+        ResponseTemplate::new(200).set_body_json(zone_mode_body("cool")),
+    )
+    .await;
+
+    let outcome = client
+        .set_hvac_mode_and_confirm(0, lennox_s30::HvacMode::Cool, Duration::from_secs(5))
+        .await
+        .unwrap();
+    assert_eq!(outcome, Confirmation::Confirmed);
+    assert_eq!(client.zone(0, 0).unwrap().mode, Some(lennox_s30::HvacMode::Cool));
+}
+
+#[tokio::test]
+async fn set_hvac_mode_and_confirm_rejected() {
+    let server = MockServer::start().await;
+    let mut client = client_with_zone(&server).await;
+    mount_publish_and_followup(
+        &server,
+        ResponseTemplate::new(200).set_body_json(zone_mode_body("off")),
+    )
+    .await;
+
+    let outcome = client
+        .set_hvac_mode_and_confirm(0, lennox_s30::HvacMode::Cool, Duration::from_millis(200))
+        .await
+        .unwrap();
+    assert_eq!(outcome, Confirmation::Rejected);
+}
+
+#[tokio::test]
+async fn set_away_and_confirm_times_out() {
+    let server = MockServer::start().await;
+    let mut client = client_with_zone(&server).await;
+    mount_publish_and_followup(&server, ResponseTemplate::new(204)).await;
+
+    let outcome = client
+        .set_away_and_confirm(true, Duration::from_millis(100))
+        .await
+        .unwrap();
+    assert_eq!(outcome, Confirmation::TimedOut);
+}
+
+#[tokio::test]
+async fn confirmation_deadline_cuts_long_poll_short() {
+    let server = MockServer::start().await;
+    let mut client = client_with_zone(&server).await;
+    mount_publish_and_followup(&server, ResponseTemplate::new(204).set_delay(Duration::from_secs(5))).await;
+
+    let started = std::time::Instant::now();
+    let outcome = client
+        .set_away_and_confirm(true, Duration::from_millis(200))
+        .await
+        .unwrap();
+    assert_eq!(outcome, Confirmation::TimedOut);
+    assert!(started.elapsed() < Duration::from_secs(2));
+}
+
+#[tokio::test]
+async fn command_reply_is_correlated_by_message_id() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path_regex(r"/Messages/Publish"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("{}"))
+        .mount(&server)
+        .await;
+
+    let events: Arc<Mutex<Vec<Event>>> = Arc::new(Mutex::new(vec![]));
+    let events_clone = events.clone();
+    let addr = server.address();
+    for mock in setup_connect_mocks() {
+        mock.mount(&server).await;
+    }
+    let mut client = S30Client::builder(format!("{}:{}", addr.ip(), addr.port()))
+        .protocol(Protocol::Http)
+        .on_event(move |event| {
+            events_clone.lock().unwrap().push(event.clone());
+        })
+        .build();
+    client.connect().await.unwrap();
+
+    client.set_away(true).await.unwrap();
+    let message_id = client.last_command_id().unwrap().to_string();
+
+    let published = server.received_requests().await.unwrap();
+    let publish_body = String::from_utf8_lossy(&published.last().unwrap().body).to_string();
+    assert!(publish_body.contains(&message_id));
+
+    let reply = serde_json::json!({
+        "messages": [{
+            "SenderID": "LCC",
+            "MessageID": "reply-1",
+            "MessageType": "CommandError",
+            "Data": {"error": {"code": 7, "requestMessageId": message_id}}
+        }]
+    });
+    Mock::given(method("GET"))
+        .and(path_regex(r"/Messages/.+/Retrieve"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&reply))
+        .mount(&server)
+        .await;
+    client.poll().await.unwrap();
+
+    let outcome = client.command_outcome(&message_id).expect("reply should be correlated");
+    assert_eq!(outcome.action, "set_away");
+    assert_eq!(outcome.message_type.as_deref(), Some("CommandError"));
+    assert!(outcome.is_error());
+
+    let captured = events.lock().unwrap();
+    assert!(captured.iter().any(|e| matches!(
+        e,
+        Event::CommandOutcome { outcome } if outcome.message_id == message_id
+    )));
+}
+
+fn outdoor_temp_body() -> serde_json::Value {
+    serde_json::json!({
+        "messages": [{
+            "SenderID": "LCC",
+            "Data": {
+                "system": {
+                    "config": { "name": "Test System" },
+                    "status": {
+                        "outdoorTemperature": 72,
+                        "outdoorTemperatureC": 22.0
+                    }
+                }
+            }
+        }]
+    })
+}
+
+#[tokio::test]
+async fn subscribe_streams_events_and_watch_tracks_state() {
+    use tokio_stream::StreamExt;
+
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path_regex(r"/Messages/.+/Retrieve"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(outdoor_temp_body()))
+        .mount(&server)
+        .await;
+
+    let mut client = connected_client(&server).await;
+    let mut first = Box::pin(client.subscribe());
+    let mut second = Box::pin(client.subscribe());
+    let mut systems = client.watch_systems();
+    assert!(systems.borrow().is_empty());
+
+    client.poll().await.unwrap();
+
+    let from_first = first.next().await.unwrap();
+    let from_second = second.next().await.unwrap();
+    assert_eq!(format!("{from_first:?}"), format!("{from_second:?}"));
+
+    assert!(systems.has_changed().unwrap());
+    let latest = systems.borrow_and_update();
+    assert_eq!(latest[0].name, "Test System");
+}
+
+#[tokio::test]
+async fn slow_subscriber_gets_lag_indicator() {
+    use tokio_stream::StreamExt;
+
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path_regex(r"/Messages/.+/Retrieve"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(outdoor_temp_body()))
+        .mount(&server)
+        .await;
+    for mock in setup_connect_mocks() {
+        mock.mount(&server).await;
+    }
+    let addr = server.address();
+    let mut client = S30Client::builder(format!("{}:{}", addr.ip(), addr.port()))
+        .protocol(Protocol::Http)
+        .event_buffer(1)
+        .build();
+    client.connect().await.unwrap();
+
+    let mut stream = Box::pin(client.subscribe());
+    client.poll().await.unwrap();
+
+    match stream.next().await.unwrap() {
+        Event::Lagged { missed } => assert!(missed > 0),
+        other => panic!("expected Lagged, got {other:?}"),
+    }
+}
+
+fn fast_reconnect() -> ReconnectPolicy {
+    ReconnectPolicy {
+        initial_delay: Duration::from_millis(10),
+        max_delay: Duration::from_millis(50),
+        multiplier: 2.0,
+    }
+}
+
+async fn mount_idle_retrieve(server: &MockServer) {
+    Mock::given(method("GET"))
+        .and(path_regex(r"/Messages/.+/Retrieve"))
+        .respond_with(ResponseTemplate::new(204).set_delay(Duration::from_millis(20)))
+        .mount(server)
+        .await;
+}
+
+fn spawnable_client(server: &MockServer) -> S30Client {
+    let addr = server.address();
+    S30Client::builder(format!("{}:{}", addr.ip(), addr.port()))
+        .protocol(Protocol::Http)
+        .reconnect_policy(fast_reconnect())
+        .build()
+}
+
+#[tokio::test]
+async fn spawned_driver_polls_sends_commands_and_shuts_down() {
+    let server = MockServer::start().await;
+    for mock in setup_connect_mocks() {
+        mock.mount(&server).await;
+    }
+    Mock::given(method("GET"))
+        .and(path_regex(r"/Messages/.+/Retrieve"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(outdoor_temp_body()))
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    mount_idle_retrieve(&server).await;
+    Mock::given(method("POST"))
+        .and(path_regex(r"/Messages/Publish"))
+        .and(body_string_contains("manualAway"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("{}"))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path_regex(r"/Endpoints/.+/Disconnect"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("{}"))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let handle = spawnable_client(&server).spawn();
+    let mut systems = handle.watch_systems();
+    tokio::time::timeout(Duration::from_secs(5), systems.changed())
+        .await
+        .expect("driver should publish state")
+        .unwrap();
+    assert_eq!(handle.systems()[0].name, "Test System");
+
+    handle.clone().set_away(true).await.unwrap();
+    handle.shutdown().await;
+
+    assert!(matches!(handle.set_away(false).await, Err(lennox_s30::Error::NotConnected)));
+}
+
+#[tokio::test]
+async fn spawned_driver_resubscribes_after_404() {
+    let server = MockServer::start().await;
+    for mock in setup_connect_mocks() {
+        mock.mount(&server).await;
+    }
+    Mock::given(method("GET"))
+        .and(path_regex(r"/Messages/.+/Retrieve"))
+        .respond_with(ResponseTemplate::new(404))
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    mount_idle_retrieve(&server).await;
+    Mock::given(method("POST"))
+        .and(path_regex(r"/Endpoints/.+/Disconnect"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("{}"))
+        .mount(&server)
+        .await;
+
+    let handle = spawnable_client(&server).spawn();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    handle.shutdown().await;
+
+    let requests = server.received_requests().await.unwrap();
+    let subscribes = requests
+        .iter()
+        .filter(|r| r.url.path() == "/Messages/RequestData")
+        .count();
+    assert_eq!(subscribes, 2);
+}
+
+#[tokio::test]
+async fn spawned_driver_retries_failed_connect() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path_regex(r"/Endpoints/.+/Connect"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(2)
+        .mount(&server)
+        .await;
+    for mock in setup_connect_mocks() {
+        mock.mount(&server).await;
+    }
+    Mock::given(method("GET"))
+        .and(path_regex(r"/Messages/.+/Retrieve"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(outdoor_temp_body()))
+        .mount(&server)
+        .await;
+
+    let handle = spawnable_client(&server).spawn();
+    let mut systems = handle.watch_systems();
+    tokio::time::timeout(Duration::from_secs(5), systems.changed())
+        .await
+        .expect("driver should connect after retries")
+        .unwrap();
+}
+
+#[tokio::test]
+async fn spawned_driver_stops_on_non_retryable_error() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path_regex(r"/Endpoints/.+/Connect"))
+        .respond_with(ResponseTemplate::new(403))
+        .mount(&server)
+        .await;
+
+    let handle = spawnable_client(&server).spawn();
+    let deadline = std::time::Instant::now() + Duration::from_secs(5);
+    while handle.connection_state() != ConnectionState::Failed {
+        assert!(std::time::Instant::now() < deadline, "driver should give up");
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let connects = server.received_requests().await.unwrap().len();
+    assert_eq!(connects, 1, "a 403 shouldn't be retried");
+    handle.shutdown().await;
+}
+
+#[test]
+fn handle_is_send_sync_clone() {
+    fn assert_shareable<T: Clone + Send + Sync + 'static>() {}
+    assert_shareable::<S30Handle>();
+}
+
+#[tokio::test]
+async fn handle_publishes_while_poll_is_blocked() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path_regex(r"/Messages/.+/Retrieve"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(zone_mode_body("heat")))
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path_regex(r"/Messages/.+/Retrieve"))
+        .respond_with(ResponseTemplate::new(204).set_delay(Duration::from_secs(10)))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path_regex(r"/Messages/Publish"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("{}"))
+        .mount(&server)
+        .await;
+
+    let mut client = connected_client(&server).await;
+    client.poll().await.unwrap();
+    let handle = client.handle();
+
+    let polling = tokio::spawn(async move { client.poll().await });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    tokio::time::timeout(Duration::from_secs(2), handle.set_hvac_mode(0, lennox_s30::HvacMode::Cool))
+        .await
+        .expect("command should not wait for the long poll")
+        .unwrap();
+    assert!(handle.last_command_id().is_some());
+    assert!(!polling.is_finished());
+    polling.abort();
+}
+
+#[tokio::test]
+async fn handle_validates_against_latest_snapshot() {
+    let server = MockServer::start().await;
+    let client = connected_client(&server).await;
+    let handle = client.handle();
+    assert!(matches!(
+        handle.set_fan_mode(3, lennox_s30::FanMode::Auto).await,
+        Err(lennox_s30::Error::InvalidZone(3))
+    ));
+}
+
+#[tokio::test]
+async fn connection_state_transitions_emit_events() {
+    use lennox_s30::ConnectionState::*;
+
+    let server = MockServer::start().await;
+    for mock in setup_connect_mocks() {
+        mock.mount(&server).await;
+    }
+    Mock::given(method("GET"))
+        .and(path_regex(r"/Messages/.+/Retrieve"))
+        .respond_with(ResponseTemplate::new(502))
+        .up_to_n_times(2)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path_regex(r"/Messages/.+/Retrieve"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(outdoor_temp_body()))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path_regex(r"/Endpoints/.+/Disconnect"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("{}"))
+        .mount(&server)
+        .await;
+
+    let transitions = Arc::new(Mutex::new(Vec::new()));
+    let seen = transitions.clone();
+    let addr = server.address();
+    let mut client = S30Client::builder(format!("{}:{}", addr.ip(), addr.port()))
+        .protocol(Protocol::Http)
+        .on_event(move |e| {
+            if let Event::ConnectionChanged { to, .. } = e {
+                seen.lock().unwrap().push(*to);
+            }
+        })
+        .build();
+    assert_eq!(client.connection_state(), Disconnected);
+
+    client.connect().await.unwrap();
+    client.poll().await.unwrap();
+    client.poll().await.unwrap();
+    assert_eq!(client.connection_health().consecutive_failures, 2);
+    assert!(client.connection_health().last_data.is_none());
+
+    client.poll().await.unwrap();
+    let health = client.connection_health();
+    assert_eq!(health.state, Subscribed);
+    assert_eq!(health.consecutive_failures, 0);
+    assert!(health.last_successful_poll.is_some());
+    assert!(health.time_since_last_data().is_some());
+
+    client.disconnect().await.unwrap();
+    assert_eq!(
+        *transitions.lock().unwrap(),
+        vec![Connecting, Subscribed, Degraded, Subscribed, Disconnected]
+    );
+}
+
+#[tokio::test]
+async fn failed_connect_counts_failures() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path_regex(r"/Endpoints/.+/Connect"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&server)
+        .await;
+    let addr = server.address();
+    let mut client = S30Client::builder(format!("{}:{}", addr.ip(), addr.port()))
+        .protocol(Protocol::Http)
+        .build();
+    assert!(client.connect().await.is_err());
+    assert!(client.connect().await.is_err());
+    let health = client.connection_health();
+    assert_eq!(health.state, lennox_s30::ConnectionState::Disconnected);
+    assert_eq!(health.consecutive_failures, 2);
+}
+
+#[tokio::test]
+async fn quiet_thermostat_reports_stale_and_resubscribes() {
+    let server = MockServer::start().await;
+    for mock in setup_connect_mocks() {
+        mock.mount(&server).await;
+    }
+    Mock::given(method("GET"))
+        .and(path_regex(r"/Messages/.+/Retrieve"))
+        .respond_with(ResponseTemplate::new(204).set_delay(Duration::from_millis(60)))
+        .mount(&server)
+        .await;
+
+    let stale = Arc::new(Mutex::new(0));
+    let seen = stale.clone();
+    let addr = server.address();
+    let mut client = S30Client::builder(format!("{}:{}", addr.ip(), addr.port()))
+        .protocol(Protocol::Http)
+        .stale_after(Duration::from_millis(50))
+        .on_event(move |e| {
+            if matches!(e, Event::DataStale { .. }) {
+                *seen.lock().unwrap() += 1;
+            }
+        })
+        .build();
+    client.connect().await.unwrap();
+
+    client.poll().await.unwrap();
+    client.poll().await.unwrap();
+    assert_eq!(*stale.lock().unwrap(), 1, "stale fires once per quiet period");
+
+    let subscribes = server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .filter(|r| r.url.path() == "/Messages/RequestData")
+        .count();
+    assert_eq!(subscribes, 3, "initial subscribe plus one per window");
+}
+
+#[tokio::test]
+async fn data_updates_freshness_timestamps() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path_regex(r"/Messages/.+/Retrieve"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(zone_mode_body("heat")))
+        .mount(&server)
+        .await;
+
+    let mut client = connected_client(&server).await;
+    client.poll().await.unwrap();
+
+    let system = &client.systems()[0];
+    assert!(system.zones[0].last_updated.is_some());
+    assert!(!system.is_stale(Duration::from_secs(60)));
+    assert!(lennox_s30::System::default().is_stale(Duration::from_secs(60)));
+}
+
+#[tokio::test]
+async fn long_poll_timeout_is_sent_to_thermostat() {
+    use wiremock::matchers::query_param;
+
+    let server = MockServer::start().await;
+    for mock in setup_connect_mocks() {
+        mock.mount(&server).await;
+    }
+    Mock::given(method("GET"))
+        .and(path_regex(r"/Messages/.+/Retrieve"))
+        .and(query_param("LongPollingTimeout", "30"))
+        .respond_with(ResponseTemplate::new(204))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let addr = server.address();
+    let mut client = S30Client::builder(format!("{}:{}", addr.ip(), addr.port()))
+        .protocol(Protocol::Http)
+        .long_poll_timeout(Duration::from_secs(30))
+        .build();
+    client.connect().await.unwrap();
+    client.poll().await.unwrap();
+}
+
+#[tokio::test]
+async fn hung_request_times_out() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path_regex(r"/Endpoints/.+/Connect"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(5)))
+        .mount(&server)
+        .await;
+
+    let addr = server.address();
+    let mut client = S30Client::builder(format!("{}:{}", addr.ip(), addr.port()))
+        .protocol(Protocol::Http)
+        .request_timeout(Duration::from_millis(100))
+        .build();
+    let started = std::time::Instant::now();
+    match client.connect().await {
+        Err(err @ lennox_s30::Error::Timeout) => assert!(err.is_retryable()),
+        other => panic!("expected timeout, got {other:?}"),
+    }
+    assert!(started.elapsed() < Duration::from_secs(2));
+}
+
+#[tokio::test]
+async fn injected_http_client_is_used() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path_regex(r"/Endpoints/.+/Connect"))
+        .and(wiremock::matchers::header("x-injected", "yes"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("{}"))
+        .expect(1)
+        .mount(&server)
+        .await;
+    for mock in setup_connect_mocks() {
+        mock.mount(&server).await;
+    }
+
+    let mut headers = reqwest::header::HeaderMap::new();
+    headers.insert("x-injected", "yes".parse().unwrap());
+    let http = reqwest::Client::builder().default_headers(headers).build().unwrap();
+
+    let addr = server.address();
+    let mut client = S30Client::builder(format!("{}:{}", addr.ip(), addr.port()))
+        .protocol(Protocol::Http)
+        .http_client(http)
+        .build();
+    client.connect().await.unwrap();
+}
+
+#[test]
+fn try_build_rejects_bad_configuration() {
+    use lennox_s30::Error;
+
+    assert!(matches!(
+        S30Client::builder("").try_build(),
+        Err(Error::InvalidHost { .. })
+    ));
+    assert!(matches!(
+        S30Client::builder("192.168.1.175/Endpoints").try_build(),
+        Err(Error::InvalidHost { .. })
+    ));
+    assert!(matches!(
+        S30Client::builder("192.168.1.175:99999").try_build(),
+        Err(Error::InvalidHost { .. })
+    ));
+    assert!(matches!(
+        S30Client::builder("192.168.1.175").app_id("my app").try_build(),
+        Err(Error::InvalidAppId { .. })
+    ));
+    assert!(matches!(
+        S30Client::builder("192.168.1.175").app_id("x".repeat(65)).try_build(),
+        Err(Error::InvalidAppId { .. })
+    ));
+    assert!(matches!(
+        S30Client::builder("192.168.1.175")
+            .message_log(lennox_s30::MessageLogMode::Full, "/nonexistent/dir/log.ndjson")
+            .try_build(),
+        Err(Error::InvalidLogPath { .. })
+    ));
+    for multiplier in [0.5, f64::NAN] {
+        let policy = ReconnectPolicy { multiplier, ..ReconnectPolicy::default() };
+        assert!(matches!(
+            S30Client::builder("192.168.1.175").reconnect_policy(policy).try_build(),
+            Err(Error::InvalidReconnectPolicy(_))
+        ));
+    }
+    assert!(matches!("ftp".parse::<Protocol>(), Err(Error::InvalidProtocol(_))));
+
+    assert_eq!("HTTP".parse::<Protocol>().unwrap(), Protocol::Http);
+    assert!(S30Client::builder("thermostat.local:8443").app_id("ha-1").try_build().is_ok());
+    assert!(S30Client::builder("[::1]:8080").protocol(Protocol::Http).try_build().is_ok());
+}
+
+#[tokio::test]
+async fn http_errors_carry_status_and_body() {
+    use lennox_s30::Error;
+
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path_regex(r"/Endpoints/.+/Connect"))
+        .respond_with(ResponseTemplate::new(403).set_body_string("endpoint limit reached"))
+        .mount(&server)
+        .await;
+
+    let addr = server.address();
+    let mut client = S30Client::builder(format!("{}:{}", addr.ip(), addr.port()))
+        .protocol(Protocol::Http)
+        .app_id("status_test")
+        .build();
+    let err = client.connect().await.unwrap_err();
+    match &err {
+        Error::Status { endpoint, status, body } => {
+            assert_eq!(endpoint, "/Endpoints/status_test/Connect");
+            assert_eq!(*status, 403);
+            assert_eq!(body, "endpoint limit reached");
+        }
+        other => panic!("expected Status, got {other:?}"),
+    }
+    assert!(!err.is_retryable());
+}
+
+#[tokio::test]
+async fn poll_404_is_retryable_status() {
+    let server = MockServer::start().await;
+    let mut client = connected_client(&server).await;
+    Mock::given(method("GET"))
+        .and(path_regex(r"/Messages/.+/Retrieve"))
+        .respond_with(ResponseTemplate::new(404))
+        .mount(&server)
+        .await;
+
+    let err = client.poll().await.unwrap_err();
+    assert_eq!(err.status(), Some(404));
+    assert!(err.is_retryable());
+}
+
+#[tokio::test]
+async fn poll_malformed_body_is_reported() {
+    let server = MockServer::start().await;
+    for mock in setup_connect_mocks() {
+        mock.mount(&server).await;
+    }
+    Mock::given(method("GET"))
+        .and(path_regex(r"/Messages/.+/Retrieve"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("<html>busy</html>"))
+        .mount(&server)
+        .await;
+
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let seen_cb = seen.clone();
+    let addr = server.address();
+    let mut client = S30Client::builder(format!("{}:{}", addr.ip(), addr.port()))
+        .protocol(Protocol::Http)
+        .on_event(move |e| {
+            if let Event::MalformedResponse { body, .. } = e {
+                seen_cb.lock().unwrap().push(body.clone());
+            }
+        })
+        .build();
+    client.connect().await.unwrap();
+
+    match client.poll().await {
+        Err(err @ lennox_s30::Error::MalformedResponse { .. }) => {
+            assert!(err.is_retryable());
+            assert!(err.to_string().starts_with("malformed response"));
+        }
+        other => panic!("expected MalformedResponse, got {other:?}"),
+    }
+    assert!(client.poll().await.is_err());
+    assert_eq!(*seen.lock().unwrap(), vec!["<html>busy</html>"; 2]);
+    assert_eq!(client.connection_health().malformed_responses, 2);
+}
+
+#[tokio::test]
+async fn poll_reports_unexpected_senders() {
+    let server = MockServer::start().await;
+    for mock in setup_connect_mocks() {
+        mock.mount(&server).await;
+    }
+    let body = serde_json::json!({"messages": [
+        {"SenderID": "LCC", "Data": {"system": {"status": {"outdoorTemperature": 70}}}},
+        {"SenderID": "lennox_s30", "MessageType": "Command", "Data": {}},
+        {"SenderID": "mystery", "MessageType": "PropertyChange", "Data": {"x": 1}}
+    ]});
+    Mock::given(method("GET"))
+        .and(path_regex(r"/Messages/.+/Retrieve"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(body))
+        .mount(&server)
+        .await;
+
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let seen_cb = seen.clone();
+    let addr = server.address();
+    let mut client = S30Client::builder(format!("{}:{}", addr.ip(), addr.port()))
+        .protocol(Protocol::Http)
+        .on_event(move |e| {
+            if let Event::UnexpectedMessage { sender_id, .. } = e {
+                seen_cb.lock().unwrap().push(sender_id.clone());
+            }
+        })
+        .build();
+    client.connect().await.unwrap();
+    client.poll().await.unwrap();
+
+    assert_eq!(*seen.lock().unwrap(), vec![Some("mystery".to_string())]);
+    let health = client.connection_health();
+    assert_eq!(health.unexpected_messages, 1);
+    assert_eq!(health.malformed_responses, 0);
+    assert!(health.last_data.is_some());
+}
+
+#[tokio::test]
+async fn replayer_honours_recorded_timing() {
+    let log = r#"{"ts":"2024-01-01T00:00:00Z","dir":"req","method":"POST","path":"/Endpoints/a/Connect","body":null}
+{"ts":"2024-01-01T00:00:00Z","dir":"poll","status":200,"body":{"messages":[{"SenderID":"LCC","Data":{"system":{"status":{"outdoorTemperature":50,"outdoorTemperatureC":10}}}}]}}
+{"ts":"2024-01-01T00:00:01Z","dir":"poll","status":204}
+{"ts":"2024-01-01T00:00:02Z","dir":"poll","status":200,"body":{"messages":[{"SenderID":"LCC","Data":{"system":{"status":{"outdoorTemperature":59,"outdoorTemperatureC":15}}}}]}}
+"#;
+    let temps = Arc::new(Mutex::new(Vec::new()));
+    let temps_cb = temps.clone();
+    let mut client = S30Client::builder("127.0.0.1")
+        .on_event(move |e| {
+            if let Event::OutdoorTempChanged { temp } = e {
+                temps_cb.lock().unwrap().push(temp.celsius());
+            }
+        })
+        .build();
+
+    let replayer = lennox_s30::Replayer::from_reader(log.as_bytes()).unwrap().speed(20.0);
+    assert_eq!(replayer.polls().len(), 3);
+    let started = std::time::Instant::now();
+    assert_eq!(replayer.run(&mut client).await, 2);
+    assert!(started.elapsed() >= Duration::from_millis(90));
+    assert!(started.elapsed() < Duration::from_secs(1));
+    assert_eq!(*temps.lock().unwrap(), vec![10.0, 15.0]);
+}
+
+#[tokio::test]
+async fn failed_polls_are_logged_with_status_and_error() {
+    let server = MockServer::start().await;
+    for mock in setup_connect_mocks() {
+        mock.mount(&server).await;
+    }
+    Mock::given(method("GET"))
+        .and(path_regex(r"/Messages/.+/Retrieve"))
+        .respond_with(ResponseTemplate::new(502))
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path_regex(r"/Messages/.+/Retrieve"))
+        .respond_with(ResponseTemplate::new(500).set_body_string("busy"))
+        .mount(&server)
+        .await;
+
+    let ring = lennox_s30::RingBufferSink::new(Duration::from_secs(60), 100);
+    let addr = server.address();
+    let mut client = S30Client::builder(format!("{}:{}", addr.ip(), addr.port()))
+        .protocol(Protocol::Http)
+        .message_sink(lennox_s30::MessageLogMode::Full, ring.clone())
+        .build();
+    client.connect().await.unwrap();
+    client.poll().await.unwrap();
+    client.poll().await.unwrap_err();
+
+    let polls: Vec<serde_json::Value> = ring
+        .entries()
+        .iter()
+        .map(|l| serde_json::from_str::<serde_json::Value>(l).unwrap())
+        .filter(|e| e["dir"] == "poll")
+        .collect();
+    assert_eq!(polls.len(), 2);
+    assert_eq!(polls[0]["status"], 502);
+    assert_eq!(polls[1]["status"], 500);
+    assert!(polls[1]["error"].as_str().unwrap().contains("busy"));
+    assert!(polls.iter().all(|p| p["latency_ms"].is_u64()));
+}
@@ -1,93 +1,251 @@
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use lennox_s30::{
+    ConnectionState, Event, HvacMode, LogHistory, MessageLogMode, Protocol, Replayer, RingBufferSink, S30Client,
+    Simulator,
+};
+use serde_json::json;
+
+const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/system_heatpump_furnace.json");
+
+async fn simulator() -> Simulator {
+    Simulator::builder()
+        .fixture(FIXTURE)
+        .start()
+        .await
+        .expect("simulator should start")
+}
 
-use lennox_s30::{Event, S30Client};
+fn client_for(sim: &Simulator, events: Arc<Mutex<Vec<Event>>>) -> S30Client {
+    S30Client::builder(sim.host())
+        .protocol(Protocol::Http)
+        .long_poll_timeout(Duration::from_secs(1))
+        .on_event(move |event| {
+            events.lock().unwrap().push(event.clone());
+        })
+        .build()
+}
 
-/// Run with: cargo test --test integration -- --ignored
-/// Requires simulator running:
-///   cd ~/home/lennoxs30api && .venv/bin/python -m aiohttp.web simulator.main:init_func \
-///     -c simulator/conf/config_heatpump_furnace.json --port 8080
 #[tokio::test]
-#[ignore]
 async fn connect_poll_disconnect() {
+    let sim = simulator().await;
     let events: Arc<Mutex<Vec<Event>>> = Arc::new(Mutex::new(vec![]));
-    let events_clone = events.clone();
-
-    let mut client = S30Client::builder("127.0.0.1:8080")
-        .protocol("http")
-        .on_event(move |event| {
-            events_clone.lock().unwrap().push(event.clone());
-        })
-        .build();
+    let mut client = client_for(&sim, events.clone());
 
     client.connect().await.expect("connect failed");
-
-    // Simulator queues multiple messages (config, equipment, devices, etc.)
-    // and returns one per poll via queue.pop() (LIFO). Poll until system data arrives.
-    for i in 0..10 {
-        client
-            .poll()
-            .await
-            .unwrap_or_else(|e| panic!("poll {i} failed: {e}"));
-        if !client.systems().is_empty() && !client.systems()[0].zones.is_empty() {
-            break;
-        }
-    }
+    assert_eq!(sim.connected_apps(), vec!["lennox_s30"]);
+    client.poll().await.expect("poll failed");
 
     let systems = client.systems();
     assert!(!systems.is_empty(), "should have at least one system");
+    assert!(!systems[0].zones.is_empty(), "should have at least one zone");
+    assert!(!events.lock().unwrap().is_empty(), "should have received events");
+
+    client.disconnect().await.expect("disconnect failed");
+    assert!(sim.connected_apps().is_empty());
+}
+
+#[tokio::test]
+async fn outdoor_temp_updates() {
+    let sim = simulator().await;
+    let events: Arc<Mutex<Vec<Event>>> = Arc::new(Mutex::new(vec![]));
+    let mut client = client_for(&sim, events.clone());
+    client.connect().await.expect("connect failed");
+    client.poll().await.expect("initial poll failed");
+
+    sim.update(json!({"system": {"status": {"outdoorTemperature": 41, "outdoorTemperatureC": 5}}}));
+    client.poll().await.expect("temp poll failed");
+
+    let captured = events.lock().unwrap();
     assert!(
-        !systems[0].zones.is_empty(),
-        "should have at least one zone"
+        captured
+            .iter()
+            .any(|e| matches!(e, Event::OutdoorTempChanged { temp } if temp.celsius() == 5.0)),
+        "expected OutdoorTempChanged, got {captured:?}"
     );
+}
 
-    {
-        let captured = events.lock().unwrap();
-        assert!(!captured.is_empty(), "should have received events");
-    }
+#[tokio::test]
+async fn published_command_is_applied_and_echoed() {
+    let sim = simulator().await;
+    let events: Arc<Mutex<Vec<Event>>> = Arc::new(Mutex::new(vec![]));
+    let mut client = client_for(&sim, events.clone());
+    client.connect().await.expect("connect failed");
+    client.poll().await.expect("initial poll failed");
+    assert_eq!(client.systems()[0].zones[0].mode, Some(HvacMode::Off));
 
-    client.disconnect().await.expect("disconnect failed");
+    client.set_hvac_mode(0, HvacMode::Heat).await.expect("set_hvac_mode failed");
+    assert_eq!(sim.published().len(), 1);
+    client.poll().await.expect("echo poll failed");
+
+    assert_eq!(client.systems()[0].zones[0].mode, Some(HvacMode::Heat));
+    assert_eq!(
+        sim.state().pointer("/zones/0/status/period/systemMode").unwrap(),
+        "heat"
+    );
+    assert!(events
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|e| matches!(e, Event::ZoneModeChanged { zone_id: 0, mode: HvacMode::Heat, .. })));
 }
 
 #[tokio::test]
-#[ignore]
-async fn outdoor_temp_updates() {
-    // Requires outdoorTempSim: true in simulator config.
-    // Cycles outdoor temp every 5s. Drain initial queue, wait for sim, then poll.
-    let events: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(vec![]));
-    let events_clone = events.clone();
+async fn idle_poll_returns_204_and_502_is_tolerated() {
+    let sim = simulator().await;
+    let mut client = client_for(&sim, Arc::default());
+    client.connect().await.expect("connect failed");
+    client.poll().await.expect("initial poll failed");
 
-    let mut client = S30Client::builder("127.0.0.1:8080")
-        .protocol("http")
-        .on_event(move |event| {
-            events_clone.lock().unwrap().push(format!("{event:?}"));
-        })
+    let started = std::time::Instant::now();
+    client.poll().await.expect("idle poll failed");
+    assert!(started.elapsed() >= Duration::from_millis(900), "204 only after the long-poll window");
+    assert_eq!(client.connection_state(), ConnectionState::Subscribed);
+
+    sim.inject_502(1);
+    client.poll().await.expect("502 is not an error");
+    assert_eq!(client.connection_state(), ConnectionState::Degraded);
+}
+
+#[tokio::test]
+async fn app_ids_have_separate_queues() {
+    let sim = simulator().await;
+    let mut a = client_for(&sim, Arc::default());
+    let mut b = S30Client::builder(sim.host())
+        .protocol(Protocol::Http)
+        .app_id("second")
+        .long_poll_timeout(Duration::from_secs(1))
         .build();
+    a.connect().await.expect("connect a failed");
+    b.connect().await.expect("connect b failed");
+    a.poll().await.expect("poll a failed");
+    assert!(!a.systems().is_empty());
 
-    client.connect().await.expect("connect failed");
+    b.poll().await.expect("poll b failed");
+    assert!(!b.systems().is_empty(), "b gets its own full state");
 
-    // Drain the initial config queue
-    for i in 0..10 {
-        client
-            .poll()
-            .await
-            .unwrap_or_else(|e| panic!("drain poll {i} failed: {e}"));
-        if !client.systems().is_empty() {
-            break;
-        }
-    }
-
-    // Wait for simulator to cycle outdoor temp
-    tokio::time::sleep(std::time::Duration::from_secs(6)).await;
-
-    // Poll for the temp update
-    client.poll().await.expect("temp poll failed");
+    b.disconnect().await.expect("disconnect b failed");
+    assert_eq!(sim.connected_apps(), vec!["lennox_s30"]);
+}
+
+#[tokio::test]
+async fn diffed_log_replays_to_same_state() {
+    let sim = simulator().await;
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("capture.ndjson");
+
+    let live_events: Arc<Mutex<Vec<Event>>> = Arc::default();
+    let mut live = S30Client::builder(sim.host())
+        .protocol(Protocol::Http)
+        .long_poll_timeout(Duration::from_secs(1))
+        .message_log(MessageLogMode::Diffed, log.to_str().unwrap())
+        .on_event({
+            let events = live_events.clone();
+            move |e| events.lock().unwrap().push(e.clone())
+        })
+        .build();
+    live.connect().await.unwrap();
+    live.poll().await.unwrap();
+    sim.update(json!({"system": {"status": {"outdoorTemperature": 41, "outdoorTemperatureC": 5}}}));
+    live.poll().await.unwrap();
+    live.set_hvac_mode(0, HvacMode::Heat).await.unwrap();
+    live.poll().await.unwrap();
+
+    let replay_events: Arc<Mutex<Vec<Event>>> = Arc::default();
+    let mut offline = S30Client::builder("127.0.0.1")
+        .on_event({
+            let events = replay_events.clone();
+            move |e| events.lock().unwrap().push(e.clone())
+        })
+        .build();
+    let replayer = Replayer::open(&log).unwrap().instant();
+    assert_eq!(replayer.run(&mut offline).await, 3);
+
+    let zone = &offline.systems()[0].zones[0];
+    assert_eq!(zone.mode, Some(HvacMode::Heat));
+    assert_eq!(
+        offline.systems()[0].outdoor_temperature,
+        live.systems()[0].outdoor_temperature
+    );
+    let data_events = |events: &Arc<Mutex<Vec<Event>>>| {
+        events
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|e| !matches!(e, Event::ConnectionChanged { .. }))
+            .map(|e| format!("{e:?}"))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(data_events(&replay_events), data_events(&live_events));
+}
 
-    {
-        let captured = events.lock().unwrap();
-        let has_outdoor = captured.iter().any(|e| e.contains("OutdoorTempChanged"));
-        println!("Outdoor temp events: {has_outdoor}");
-        println!("Total events: {}", captured.len());
-    }
+#[tokio::test]
+async fn message_log_is_an_audit_trail() {
+    let sim = simulator().await;
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("audit.ndjson");
+    let mut client = S30Client::builder(sim.host())
+        .protocol(Protocol::Http)
+        .long_poll_timeout(Duration::from_secs(1))
+        .diag_level(2)
+        .message_log(MessageLogMode::Full, log.to_str().unwrap())
+        .build();
+    client.connect().await.unwrap();
+    client.poll().await.unwrap();
+    client.set_hvac_mode(0, HvacMode::Heat).await.unwrap();
+    client.poll().await.unwrap();
+    client.disconnect().await.unwrap();
+    drop(client);
+
+    let entries: Vec<serde_json::Value> = std::fs::read_to_string(&log)
+        .unwrap()
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    let by_dir = |dir: &str| entries.iter().filter(|e| e["dir"] == dir).collect::<Vec<_>>();
+
+    let requests = by_dir("req");
+    let paths: Vec<&str> = requests.iter().map(|e| e["path"].as_str().unwrap()).collect();
+    assert_eq!(
+        paths,
+        ["/Endpoints/lennox_s30/Connect", "/Messages/RequestData", "/Endpoints/lennox_s30/Disconnect"]
+    );
+    assert!(requests.iter().all(|e| e["status"] == 200 && e["latency_ms"].is_u64()));
+
+    let commands = by_dir("cmd");
+    assert_eq!(commands[0]["action"], "set_diag_level");
+    assert!(commands.iter().all(|e| e["status"] == 200 && e["id"].is_string()));
+    let tagged: Vec<&serde_json::Value> = by_dir("poll")
+        .iter()
+        .filter_map(|e| e.get("cmds"))
+        .flat_map(|c| c.as_array().unwrap())
+        .collect();
+    let ids: Vec<&serde_json::Value> = commands.iter().map(|e| &e["id"]).collect();
+    assert_eq!(tagged, ids, "each command is tied to the poll that followed it");
+
+    let conns: Vec<&str> = by_dir("conn").iter().map(|e| e["event"].as_str().unwrap()).collect();
+    assert_eq!(conns, ["connect", "disconnect"]);
+}
 
-    client.disconnect().await.expect("disconnect failed");
+#[tokio::test]
+async fn ring_buffer_sink_dumps_replayable_log() {
+    let sim = simulator().await;
+    let ring = RingBufferSink::new(Duration::from_secs(3600), 10_000);
+    let mut client = S30Client::builder(sim.host())
+        .protocol(Protocol::Http)
+        .long_poll_timeout(Duration::from_secs(1))
+        .message_sink(MessageLogMode::Diffed, ring.clone())
+        .build();
+    client.connect().await.unwrap();
+    client.poll().await.unwrap();
+    client.set_hvac_mode(0, HvacMode::Heat).await.unwrap();
+    client.poll().await.unwrap();
+
+    let mut dump = Vec::new();
+    ring.dump(&mut dump).unwrap();
+    let history = LogHistory::from_reader(dump.as_slice()).unwrap();
+    let systems = history.systems_at(chrono::Utc::now());
+    assert_eq!(systems[0].zones[0].mode, Some(HvacMode::Heat));
+    assert_eq!(systems[0].zones[0].mode, client.systems()[0].zones[0].mode);
 }
@@ -0,0 +1,182 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use lennox_s30::{Error, Event, Fault, Protocol, ReconnectPolicy, S30Client, Simulator};
+use serde_json::json;
+
+const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/system_heatpump_furnace.json");
+
+async fn simulator() -> Simulator {
+    Simulator::builder()
+        .fixture(FIXTURE)
+        .start()
+        .await
+        .expect("simulator should start")
+}
+
+fn builder(sim: &Simulator) -> lennox_s30::S30ClientBuilder {
+    S30Client::builder(sim.host())
+        .protocol(Protocol::Http)
+        .long_poll_timeout(Duration::from_secs(1))
+        .reconnect_policy(ReconnectPolicy {
+            initial_delay: Duration::from_millis(10),
+            max_delay: Duration::from_millis(50),
+            multiplier: 2.0,
+        })
+}
+
+async fn connected(sim: &Simulator, events: Arc<Mutex<Vec<Event>>>) -> S30Client {
+    let mut client = builder(sim)
+        .on_event(move |e| events.lock().unwrap().push(e.clone()))
+        .build();
+    client.connect().await.expect("connect failed");
+    client.poll().await.expect("initial poll failed");
+    client
+}
+
+async fn eventually(what: &str, check: impl Fn() -> bool) {
+    for _ in 0..250 {
+        if check() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("timed out waiting for {what}");
+}
+
+fn outdoor_c(c: f64) -> serde_json::Value {
+    let f = (c * 9.0 / 5.0 + 32.0).round();
+    json!({"system": {"status": {"outdoorTemperature": f, "outdoorTemperatureC": c}}})
+}
+
+#[tokio::test]
+async fn driver_resubscribes_after_502_burst() {
+    let sim = simulator().await;
+    let handle = builder(&sim).build().spawn();
+    eventually("initial data", || !handle.systems().is_empty()).await;
+
+    sim.inject_502(5);
+    eventually("re-subscribe", || sim.connects() >= 2).await;
+    sim.update(outdoor_c(5.0));
+    eventually("update after recovery", || {
+        handle.systems()[0].outdoor_temperature.is_some_and(|t| t.celsius() == 5.0)
+    })
+    .await;
+    handle.shutdown().await;
+}
+
+#[tokio::test]
+async fn driver_reconnects_after_forgotten_endpoint() {
+    let sim = simulator().await;
+    let handle = builder(&sim).build().spawn();
+    eventually("initial data", || !handle.systems().is_empty()).await;
+
+    sim.inject(Fault::ForgetEndpoint);
+    eventually("reconnect", || sim.connects() >= 2).await;
+    sim.update(outdoor_c(6.0));
+    eventually("update after reconnect", || {
+        handle.systems()[0].outdoor_temperature.is_some_and(|t| t.celsius() == 6.0)
+    })
+    .await;
+    handle.shutdown().await;
+}
+
+#[tokio::test]
+async fn hung_poll_times_out_then_recovers() {
+    let sim = simulator().await;
+    let mut client = builder(&sim)
+        .request_timeout(Duration::from_millis(200))
+        .build();
+    client.connect().await.unwrap();
+    client.poll().await.unwrap();
+
+    sim.inject(Fault::Hang(Duration::from_secs(5)));
+    let err = client.poll().await.unwrap_err();
+    assert!(matches!(err, Error::Timeout), "got {err:?}");
+
+    sim.update(outdoor_c(7.0));
+    client.poll().await.unwrap();
+    assert_eq!(client.systems()[0].outdoor_temperature.unwrap().celsius(), 7.0);
+}
+
+#[tokio::test]
+async fn truncated_body_is_reported() {
+    let sim = simulator().await;
+    let mut client = connected(&sim, Arc::default()).await;
+
+    sim.inject(Fault::Truncate);
+    sim.update(outdoor_c(8.0));
+    assert!(matches!(client.poll().await, Err(Error::MalformedResponse { .. })));
+    assert_eq!(client.connection_health().malformed_responses, 1);
+}
+
+#[tokio::test]
+async fn reordered_updates_leave_older_value() {
+    let sim = simulator().await;
+    let events: Arc<Mutex<Vec<Event>>> = Arc::default();
+    let mut client = connected(&sim, events.clone()).await;
+    events.lock().unwrap().clear();
+
+    sim.update(outdoor_c(5.0));
+    sim.update(outdoor_c(10.0));
+    sim.inject(Fault::Reorder);
+    client.poll().await.unwrap();
+
+    let temps: Vec<f64> = events
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(|e| match e {
+            Event::OutdoorTempChanged { temp } => Some(temp.celsius()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(temps, vec![10.0, 5.0]);
+    assert_eq!(client.systems()[0].outdoor_temperature.unwrap().celsius(), 5.0);
+}
+
+#[tokio::test]
+async fn diag_level_reset_respects_cooldown_and_reconnect_restores_it() {
+    let sim = simulator().await;
+    let mut client = builder(&sim).diag_level(2).build();
+    client.connect().await.unwrap();
+    client.poll().await.unwrap();
+    assert_eq!(sim.state().pointer("/system/status/diagLevel").unwrap(), 2);
+    assert_eq!(client.systems()[0].diag_level, Some(2));
+
+    sim.reset_diag_level();
+    client.poll().await.unwrap();
+    assert_eq!(client.systems()[0].diag_level, Some(0));
+    assert_eq!(sim.published().len(), 1, "no reassertion inside the cooldown");
+
+    sim.inject(Fault::ForgetEndpoint);
+    assert_eq!(client.poll().await.unwrap_err().status(), Some(404));
+    client.connect().await.unwrap();
+    assert_eq!(sim.published().len(), 2);
+    assert_eq!(sim.state().pointer("/system/status/diagLevel").unwrap(), 2);
+}
+
+#[tokio::test]
+async fn alerts_appear_and_clear() {
+    let sim = simulator().await;
+    let events: Arc<Mutex<Vec<Event>>> = Arc::default();
+    let mut client = connected(&sim, events.clone()).await;
+
+    sim.raise_alert(18);
+    client.poll().await.unwrap();
+    assert!(client.systems()[0].hp_low_ambient_lockout);
+
+    sim.clear_alert(18);
+    client.poll().await.unwrap();
+    assert!(!client.systems()[0].hp_low_ambient_lockout);
+
+    let events = events.lock().unwrap();
+    let lockouts: Vec<bool> = events
+        .iter()
+        .filter_map(|e| match e {
+            Event::HpLockoutChanged { locked_out } => Some(*locked_out),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(lockouts, vec![true, false]);
+}
@@ -0,0 +1,87 @@
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use lennox_s30::{certificate_fingerprint, Error, MemoryFingerprintStore, S30Client};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::TlsAcceptor;
+
+const CERT_A: &[u8] = include_bytes!("fixtures/tls/a.crt");
+const KEY_A: &[u8] = include_bytes!("fixtures/tls/a.key");
+const CERT_B: &[u8] = include_bytes!("fixtures/tls/b.crt");
+const KEY_B: &[u8] = include_bytes!("fixtures/tls/b.key");
+
+fn server_config(cert: &[u8], key: &[u8]) -> Arc<ServerConfig> {
+    let certs = vec![CertificateDer::from_pem_slice(cert).unwrap()];
+    let key = PrivateKeyDer::from_pem_slice(key).unwrap();
+    let provider = Arc::new(tokio_rustls::rustls::crypto::ring::default_provider());
+    Arc::new(
+        ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .unwrap(),
+    )
+}
+
+fn fingerprint(cert: &[u8]) -> String {
+    certificate_fingerprint(&CertificateDer::from_pem_slice(cert).unwrap())
+}
+
+/// HTTPS server answering every request with `200 {}`. The certificate it
+/// presents can be swapped between connections.
+async fn tls_server(config: Arc<Mutex<Arc<ServerConfig>>>) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (tcp, _) = listener.accept().await.unwrap();
+            let acceptor = TlsAcceptor::from(config.lock().unwrap().clone());
+            tokio::spawn(async move {
+                let Ok(mut tls) = acceptor.accept(tcp).await else {
+                    return;
+                };
+                let mut buf = [0u8; 4096];
+                let _ = tls.read(&mut buf).await;
+                let _ = tls
+                    .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\n{}")
+                    .await;
+                let _ = tls.shutdown().await;
+            });
+        }
+    });
+    addr
+}
+
+#[tokio::test]
+async fn pins_on_first_use_and_rejects_changed_certificate() {
+    let config = Arc::new(Mutex::new(server_config(CERT_A, KEY_A)));
+    let addr = tls_server(config.clone()).await;
+    let store = Arc::new(MemoryFingerprintStore::new());
+
+    let mut client = S30Client::builder(addr.to_string())
+        .pin_certificate(store.clone())
+        .build();
+    client.connect().await.expect("first connect pins the certificate");
+    assert_eq!(client.pinned_fingerprint().unwrap(), Some(fingerprint(CERT_A)));
+
+    *config.lock().unwrap() = server_config(CERT_B, KEY_B);
+    let mut client = S30Client::builder(addr.to_string())
+        .pin_certificate(store.clone())
+        .build();
+    match client.connect().await {
+        Err(Error::CertificateMismatch { expected, actual, .. }) => {
+            assert_eq!(expected, fingerprint(CERT_A));
+            assert_eq!(actual, fingerprint(CERT_B));
+        }
+        other => panic!("expected CertificateMismatch, got {other:?}"),
+    }
+
+    client.repin_certificate().unwrap();
+    client.connect().await.expect("re-pinned certificate is accepted");
+    assert_eq!(client.pinned_fingerprint().unwrap(), Some(fingerprint(CERT_B)));
+}